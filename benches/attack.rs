@@ -1,40 +1,51 @@
-#![feature(test)]
-extern crate test;
-
+use criterion::{criterion_group, criterion_main, Criterion};
 use lpn::{
     bkw::partition_reduce,
     lf1::xor_reduce,
     oracle::{LpnOracle, MAX_K},
 };
-use test::Bencher;
 
 const LARGE_K: u32 = (MAX_K - 10) as u32;
 
-#[bench]
-fn bench_oracle_right_size_k(b: &mut Bencher) {
+fn bench_oracle_right_size_k(c: &mut Criterion) {
     let oracle = LpnOracle::new(LARGE_K, 1.0 / 8.0);
 
-    b.iter(|| oracle.clone().get_samples(100_000))
+    c.bench_function("oracle_right_size_k", |b| {
+        b.iter(|| oracle.clone().get_samples(100_000))
+    });
 }
 
-#[bench]
-fn bench_oracle_undersized_k(b: &mut Bencher) {
-    let oracle = LpnOracle::new(10 as u32, 1.0 / 8.0);
+fn bench_oracle_undersized_k(c: &mut Criterion) {
+    let oracle = LpnOracle::new(10, 1.0 / 8.0);
 
-    b.iter(|| oracle.clone().get_samples(100_000))
+    c.bench_function("oracle_undersized_k", |b| {
+        b.iter(|| oracle.clone().get_samples(100_000))
+    });
 }
 
-#[bench]
-fn bench_partition_reduce(b: &mut Bencher) {
+fn bench_partition_reduce(c: &mut Criterion) {
     let mut oracle = LpnOracle::new(LARGE_K, 1.0 / 8.0);
     oracle.get_samples(100_000);
 
-    b.iter(|| partition_reduce(&mut (oracle.clone()), 7));
+    c.bench_function("partition_reduce", |b| {
+        b.iter(|| partition_reduce(&mut (oracle.clone()), 7))
+    });
 }
-#[bench]
-fn bench_xor_reduce(b: &mut Bencher) {
+
+fn bench_xor_reduce(c: &mut Criterion) {
     let mut oracle = LpnOracle::new(LARGE_K, 1.0 / 8.0);
     oracle.get_samples(10_000);
 
-    b.iter(|| xor_reduce(&mut (oracle.clone()), 8));
+    c.bench_function("xor_reduce", |b| {
+        b.iter(|| xor_reduce(&mut (oracle.clone()), 8))
+    });
 }
+
+criterion_group!(
+    benches,
+    bench_oracle_right_size_k,
+    bench_oracle_undersized_k,
+    bench_partition_reduce,
+    bench_xor_reduce
+);
+criterion_main!(benches);
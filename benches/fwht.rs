@@ -1,35 +1,34 @@
-#![feature(test)]
-
-extern crate test;
-
+use criterion::{criterion_group, criterion_main, Criterion};
 use rand::prelude::*;
 
-use test::Bencher;
-
 use lpn::lf1::{fwht, parfwht};
 
 const BITS: u32 = 10;
 
-#[bench]
-fn fwht_speed(b: &mut Bencher) {
+fn random_counters() -> Vec<i64> {
     let mut majority_counter = vec![0; 2usize.pow(BITS)];
     let rng = &mut rand::thread_rng();
     majority_counter.iter_mut().for_each(|el| {
         *el = (rng).gen::<i64>() % 2i64.pow(16);
     });
+    majority_counter
+}
+
+fn fwht_speed(c: &mut Criterion) {
+    let majority_counter = random_counters();
 
-    b.iter(|| {
-        fwht(&mut majority_counter.clone(), BITS);
+    c.bench_function("fwht", |b| {
+        b.iter(|| fwht(&mut majority_counter.clone(), BITS))
     });
 }
 
-#[bench]
-fn parfwht_speed(b: &mut Bencher) {
-    let mut majority_counter = vec![0; 2usize.pow(BITS)];
-    let rng = &mut rand::thread_rng();
-    majority_counter.iter_mut().for_each(|el| {
-        *el = (rng).gen::<i64>() % 2i64.pow(16);
-    });
+fn parfwht_speed(c: &mut Criterion) {
+    let majority_counter = random_counters();
 
-    b.iter(|| parfwht(&mut majority_counter.clone(), BITS));
+    c.bench_function("parfwht", |b| {
+        b.iter(|| parfwht(&mut majority_counter.clone(), BITS))
+    });
 }
+
+criterion_group!(benches, fwht_speed, parfwht_speed);
+criterion_main!(benches);
@@ -1,15 +1,18 @@
-#![feature(test)]
-extern crate test;
-
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
 use lpn::oracle::*;
-use test::Bencher;
 
 const LARGE_K: u32 = (MAX_K - 10) as u32;
 
-#[bench]
-fn bench_get_samples(b: &mut Bencher) {
+fn bench_get_samples(c: &mut Criterion) {
     let oracle = LpnOracle::new(LARGE_K, 1.0 / 8.0);
     let count = 1_000_000;
-    b.bytes = count as u64;
-    b.iter(|| oracle.clone().get_samples_drop(count, 6))
+    let mut group = c.benchmark_group("samples_drop");
+    group.throughput(Throughput::Elements(count as u64));
+    group.bench_function("get_samples_drop", |b| {
+        b.iter(|| oracle.clone().get_samples_drop(count, 6))
+    });
+    group.finish();
 }
+
+criterion_group!(benches, bench_get_samples);
+criterion_main!(benches);
@@ -1,48 +1,45 @@
-#![feature(test)]
-extern crate lpn;
-extern crate m4ri_rust;
-extern crate test;
+use criterion::{criterion_group, criterion_main, Criterion};
+use lpn::codes::*;
+use m4ri_rust::friendly::*;
 
-#[cfg(feature = "stgen")]
-mod stgen {
-    use crate::test::Bencher;
-    use lpn::codes::*;
-    use m4ri_rust::friendly::*;
-
-    fn get_code() -> StGenCode<'static> {
-        let codes: Vec<&dyn BinaryCode> = vec![
-            &HammingCode7_4,
-            &HammingCode7_4,
-            &HammingCode7_4,
-            &HammingCode7_4,
-            &HammingCode7_4,
-            &HammingCode7_4,
-            &HammingCode7_4,
-            &HammingCode7_4,
-            &HammingCode7_4,
-            &HammingCode7_4,
-            &HammingCode7_4,
-        ];
-        StGenCode::new(codes, 5, 100, 4, 1)
-    }
+fn get_code() -> StGenCode<'static> {
+    let codes: Vec<&dyn BinaryCode> = vec![
+        &HammingCode7_4,
+        &HammingCode7_4,
+        &HammingCode7_4,
+        &HammingCode7_4,
+        &HammingCode7_4,
+        &HammingCode7_4,
+        &HammingCode7_4,
+        &HammingCode7_4,
+        &HammingCode7_4,
+        &HammingCode7_4,
+        &HammingCode7_4,
+    ];
+    StGenCode::new(codes, 5, 100, 4, 1)
+}
 
-    #[bench]
-    fn stgen_encode(b: &mut Bencher) {
-        let code = get_code();
+fn stgen_encode(c: &mut Criterion) {
+    let code = get_code();
 
+    c.bench_function("stgen_encode", |b| {
         b.iter(|| {
             let i = BinVector::random(code.dimension());
             code.encode(&i)
-        });
-    }
+        })
+    });
+}
 
-    #[bench]
-    fn decode(b: &mut Bencher) {
-        let code = get_code();
+fn decode(c: &mut Criterion) {
+    let code = get_code();
 
+    c.bench_function("stgen_decode", |b| {
         b.iter(|| {
             let i = BinVector::random(code.length());
             code.decode_to_message(&i).unwrap()
-        });
-    }
+        })
+    });
 }
+
+criterion_group!(benches, stgen_encode, decode);
+criterion_main!(benches);
@@ -0,0 +1,130 @@
+//! Sample amplification for when the oracle only gives a limited number of samples
+//!
+//! Following Lyubashevsky, we build many new samples by adding small random subsets
+//! of a limited pool of samples. The new samples are not independent: two samples that
+//! share a sample from the pool have correlated noise. As long as the pool is large compared
+//! to the square of the subset size, the existing reductions and solvers still work.
+use crate::{
+    oracle::{with_samples, LpnOracle},
+    random::lpn_thread_rng,
+    util::log_2,
+};
+use fnv::FnvHashSet;
+use rayon::prelude::*;
+
+/// Describes how amplified samples were obtained, to judge how independent they are
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Amplification {
+    /// The number of samples in the original pool
+    pub pool_size: usize,
+    /// The number of samples from the pool added to obtain each new sample
+    pub subset_size: usize,
+    /// The number of new samples
+    pub samples: usize,
+}
+
+impl Amplification {
+    /// The probability that two new samples share at least one sample of the pool
+    pub fn overlap_probability(&self) -> f64 {
+        1.0 - (0..self.subset_size)
+            .map(|i| (self.pool_size - self.subset_size - i) as f64 / (self.pool_size - i) as f64)
+            .product::<f64>()
+    }
+
+    /// The expected number of times each sample of the pool is used
+    pub fn reuse(&self) -> f64 {
+        (self.samples * self.subset_size) as f64 / self.pool_size as f64
+    }
+}
+
+/// Amplification reduction
+///
+/// Replaces the samples by `n` sums of distinct random subsets of `c` samples.
+///
+/// `$k' = k$`
+/// `$n' = n$`
+/// `$d' = d^c$`
+pub fn amplify_reduce(oracle: &mut LpnOracle, n: usize, c: usize) -> Amplification {
+    let pool_size = oracle.samples.len();
+    assert!(c > 1, "Adding fewer than two samples doesn't amplify");
+    assert!(pool_size > 2 * c, "The pool should be larger than 2c");
+    let subsets = (0..c)
+        .map(|i| (pool_size - i) as f64 / (i + 1) as f64)
+        .product::<f64>();
+    assert!(
+        (n as f64) < subsets / 2.0,
+        "Can't get {} distinct subsets of size {} from {} samples",
+        n,
+        c,
+        pool_size
+    );
+
+    let amplification = Amplification {
+        pool_size,
+        subset_size: c,
+        samples: n,
+    };
+    log::info!(
+        "Amplifying {} samples to {} (2^{}) sums of {} samples, delta {} -> {}",
+        pool_size,
+        n,
+        log_2(n),
+        c,
+        oracle.delta,
+        oracle.delta.powi(c as i32)
+    );
+    log::info!(
+        "Each sample of the pool is used about {:.1} times, two new samples share a sample with probability {:.5}",
+        amplification.reuse(),
+        amplification.overlap_probability()
+    );
+    if (c * c) as f64 > pool_size as f64 / 10.0 {
+        log::warn!(
+            "The subsets are large compared to the pool, the new samples are far from independent"
+        );
+    }
+
+    // pick distinct subsets
+    let mut rng = lpn_thread_rng();
+    let mut seen = FnvHashSet::with_capacity_and_hasher(n, Default::default());
+    while seen.len() < n {
+        let mut subset = rand::seq::index::sample(&mut rng, pool_size, c).into_vec();
+        subset.sort_unstable();
+        seen.insert(subset);
+    }
+
+    with_samples!(&mut oracle.samples, pool => {
+        *pool = seen
+            .into_par_iter()
+            .map(|subset| {
+                let mut sample = pool[subset[0]].clone();
+                for &idx in &subset[1..] {
+                    sample.xor_into(&pool[idx]);
+                }
+                sample
+            })
+            .collect::<Vec<_>>();
+    });
+    oracle.delta = oracle.delta.powi(c as i32);
+    amplification
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lf1::fwht_solve;
+
+    #[test]
+    fn amplify_then_fwht() {
+        let mut oracle = LpnOracle::new(16, 1.0 / 32.0);
+        oracle.set_query_budget(300);
+        oracle.get_samples(300);
+        let secret = oracle.secret.as_binvector(16);
+
+        let amplification = amplify_reduce(&mut oracle, 50_000, 3);
+        assert_eq!(oracle.samples.len(), 50_000);
+        assert_eq!(oracle.queries(), 300);
+        assert!(amplification.overlap_probability() < 0.05);
+        assert_eq!(fwht_solve(oracle), secret);
+    }
+}
@@ -0,0 +1,227 @@
+//! Statistics of the samples of an oracle, to debug attack runs
+//!
+//! All statistics are computed in parallel and do not change the oracle.
+//! They can be exported as JSON, or as CSV histograms for plotting.
+use crate::{
+    oracle::{inner_products, query_bits_range, with_samples, LpnOracle},
+    util::log_2,
+};
+use rayon::prelude::*;
+use serde_derive::Serialize;
+use std::{
+    io::{self, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// A histogram of non-negative integer values: `counts[value]` is how often `value` occurs
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Histogram {
+    pub counts: Vec<usize>,
+}
+
+impl Histogram {
+    fn from_values<I: ParallelIterator<Item = usize>>(values: I) -> Histogram {
+        let counts = values
+            .fold(Vec::new, |mut counts, value| {
+                if counts.len() <= value {
+                    counts.resize(value + 1, 0);
+                }
+                counts[value] += 1;
+                counts
+            })
+            .reduce(Vec::new, |mut a, b| {
+                if a.len() < b.len() {
+                    a.resize(b.len(), 0);
+                }
+                a.iter_mut().zip(b).for_each(|(a, b)| *a += b);
+                a
+            });
+        Histogram { counts }
+    }
+
+    /// The number of values in the histogram
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    /// The mean of the values
+    pub fn mean(&self) -> f64 {
+        self.counts
+            .iter()
+            .enumerate()
+            .map(|(value, &count)| (value * count) as f64)
+            .sum::<f64>()
+            / self.total() as f64
+    }
+
+    /// The largest value that occurs
+    pub fn max(&self) -> Option<usize> {
+        self.counts.iter().rposition(|&count| count > 0)
+    }
+
+    /// Write the histogram as CSV with the columns `value,count`
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "value,count")?;
+        for (value, count) in self.counts.iter().enumerate() {
+            writeln!(writer, "{},{}", value, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// The number of samples that are equal to an earlier sample
+pub fn count_duplicates(oracle: &LpnOracle) -> usize {
+    with_samples!(&oracle.samples, samples => {
+        let mut samples = samples.par_iter().collect::<Vec<_>>();
+        samples.par_sort_unstable();
+        samples.par_windows(2).filter(|w| w[0] == w[1]).count()
+    })
+}
+
+/// The distribution of the Hamming weights of the `a` of the samples
+pub fn weight_distribution(oracle: &LpnOracle) -> Histogram {
+    with_samples!(&oracle.samples, samples => {
+        Histogram::from_values(samples.par_iter().map(|q| q.count_ones() as usize))
+    })
+}
+
+/// The distribution of the sizes of the partitions on the last `b` bits
+///
+/// These are the partitions the next BKW-style reduction with window `b` works on.
+/// `counts[size]` is the number of windows that occur in `size` samples.
+pub fn partition_sizes(oracle: &LpnOracle, b: u32) -> Histogram {
+    let k = oracle.get_k();
+    let b = b as usize;
+    assert!(b <= k, "The window can't be larger than k");
+    assert!(b < 40, "Don't use too large b! b = {}", b);
+    let bitrange = (k - b)..k;
+
+    let mut sizes = Vec::new();
+    sizes.resize_with(1 << b, || AtomicUsize::new(0));
+    with_samples!(&oracle.samples, samples => samples.par_iter().for_each(|q| {
+        sizes[query_bits_range(q, bitrange.clone()) as usize].fetch_add(1, Ordering::Relaxed);
+    }));
+    Histogram::from_values(sizes.into_par_iter().map(AtomicUsize::into_inner))
+}
+
+/// The empirical bias of the samples with respect to the secret of the oracle
+///
+/// Returns `None` if the secret is unknown, as for imported problems.
+/// Compare with `oracle.delta`, the bias the reductions predict.
+pub fn empirical_bias(oracle: &LpnOracle) -> Option<f64> {
+    let k = oracle.get_k();
+    assert!(!oracle.samples.is_empty(), "What, no samples?");
+    if !oracle.is_secret_known() {
+        return None;
+    }
+    // with the product bit set, the inner products give the errors
+    let mut secret = oracle.secret.clone();
+    secret.truncate(k, true);
+    secret.set_product(true);
+    let errors = with_samples!(&oracle.samples, samples => {
+        let secret = secret.resize();
+        samples
+            .par_chunks(1 << 12)
+            .map(|samples| {
+                let mut errors = vec![false; samples.len()];
+                inner_products(samples, &secret, &mut errors);
+                errors.into_iter().filter(|&error| error).count()
+            })
+            .sum::<usize>()
+    });
+    Some(1.0 - 2.0 * errors as f64 / oracle.samples.len() as f64)
+}
+
+/// An overview of the statistics of the samples of an oracle
+#[derive(Debug, Clone, Serialize)]
+pub struct SampleStatistics {
+    pub k: usize,
+    pub samples: usize,
+    pub duplicates: usize,
+    /// The bias predicted by the reductions
+    pub delta: f64,
+    /// The bias measured with the secret, if known
+    pub empirical_bias: Option<f64>,
+    pub weight_distribution: Histogram,
+    /// The window size used for `partition_sizes`
+    pub window: Option<u32>,
+    pub partition_sizes: Option<Histogram>,
+}
+
+impl SampleStatistics {
+    /// Collect the statistics of the samples of `oracle`
+    ///
+    /// Includes the partition sizes on the last `window` bits, if given.
+    pub fn new(oracle: &LpnOracle, window: Option<u32>) -> SampleStatistics {
+        log::debug!(
+            "Collecting statistics of {} samples (2^{})",
+            oracle.samples.len(),
+            log_2(oracle.samples.len())
+        );
+        SampleStatistics {
+            k: oracle.get_k(),
+            samples: oracle.samples.len(),
+            duplicates: count_duplicates(oracle),
+            delta: oracle.delta,
+            empirical_bias: empirical_bias(oracle),
+            weight_distribution: weight_distribution(oracle),
+            window,
+            partition_sizes: window.map(|b| partition_sizes(oracle, b)),
+        }
+    }
+
+    /// Export the statistics as JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Can't serialize statistics")
+    }
+
+    /// Write the histograms as CSV with the columns `histogram,value,count`
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "histogram,value,count")?;
+        let histograms = std::iter::once(("weight", &self.weight_distribution))
+            .chain(self.partition_sizes.iter().map(|h| ("partition_size", h)));
+        for (name, histogram) in histograms {
+            for (value, count) in histogram.counts.iter().enumerate() {
+                writeln!(writer, "{},{},{}", name, value, count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn statistics() {
+        let mut oracle = LpnOracle::new(64, 1.0 / 8.0);
+        oracle.get_samples(50_000);
+        let mut samples = oracle.samples.to_vec();
+        samples.push(samples[17].clone());
+        oracle.samples.set(samples);
+
+        let statistics = SampleStatistics::new(&oracle, Some(8));
+        assert_eq!(statistics.samples, 50_001);
+        assert_eq!(statistics.duplicates, 1);
+        assert!((statistics.empirical_bias.unwrap() - 0.75).abs() < 0.02);
+        assert_eq!(statistics.weight_distribution.total(), 50_001);
+        assert!((statistics.weight_distribution.mean() - 32.0).abs() < 0.2);
+        let partition_sizes = statistics.partition_sizes.as_ref().unwrap();
+        assert_eq!(partition_sizes.total(), 256);
+        assert!((partition_sizes.mean() - 50_001.0 / 256.0).abs() < 1e-6);
+
+        let json: serde_json::Value = serde_json::from_str(&statistics.to_json()).unwrap();
+        assert_eq!(json["duplicates"], 1);
+        assert_eq!(json["window"], 8);
+
+        let mut csv = Vec::new();
+        statistics.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(
+            csv.lines().count(),
+            1 + statistics.weight_distribution.counts.len() + partition_sizes.counts.len()
+        );
+        assert!(csv.lines().any(|line| line.starts_with("partition_size,")));
+    }
+}
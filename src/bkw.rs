@@ -20,45 +20,302 @@ pub fn bkw(mut oracle: LpnOracle, a: u32, b: u32) -> BinVector {
     majority(oracle)
 }
 
-pub(crate) fn create_pivots(
-    oracle_samples: &mut [Sample],
+/// Partition the samples by the bits in `bitrange`
+///
+/// Sorts the samples by their window using [`radix_partition`].
+/// Returns the end of the partition of every window: window `j` is in `pivots[j-1]..pivots[j]`.
+pub(crate) fn create_pivots<const N: usize>(
+    oracle_samples: &mut [Sample<N>],
+    bitrange: &std::ops::Range<usize>,
+) -> Vec<usize> {
+    let mut pivots = radix_partition(oracle_samples, bitrange);
+    let mut end = 0;
+    for pivot in pivots.iter_mut() {
+        end += *pivot;
+        *pivot = end;
+    }
+    pivots
+}
+
+/// The number of bits per pass of the radix partitioning
+const RADIX_BITS: usize = 8;
+/// Slices smaller than this are sorted by comparison
+const RADIX_CUTOFF: usize = 64;
+/// Slices at least this large are scattered in parallel instead of sorted in place
+const PARALLEL_SCATTER_CUTOFF: usize = 1 << 16;
+/// The number of samples every thread counts and scatters in the parallel scatter
+const SCATTER_CHUNK: usize = 1 << 14;
+
+/// In-place parallel MSD radix partitioning of the samples by the bits in `bitrange`
+///
+/// Partitions large slices with a parallel scatter and small slices with American flag sort,
+/// on `RADIX_BITS` bits at a time, and recurses on the buckets in parallel.
+/// Returns the number of samples for every window.
+pub(crate) fn radix_partition<const N: usize>(
+    oracle_samples: &mut [Sample<N>],
     bitrange: &std::ops::Range<usize>,
 ) -> Vec<usize> {
-    let maxj = 2usize.pow(bitrange.len() as u32);
+    let bits = bitrange.len();
+    assert!(bits < 64, "Window should fit in a u64");
+    let mut counts = vec![0; 1 << bits];
+    let key = |q: &Sample<N>| query_bits_range(q, bitrange.clone());
+    radix_partition_rec(oracle_samples, &key, bits, &mut counts);
+    counts
+}
+
+fn radix_partition_rec<F, const N: usize>(
+    samples: &mut [Sample<N>],
+    key: &F,
+    bits: usize,
+    counts: &mut [usize],
+) where
+    F: Fn(&Sample<N>) -> u64 + Sync,
+{
+    debug_assert_eq!(counts.len(), 1 << bits);
+    let mask = (1u64 << bits) - 1;
+    if bits == 0 {
+        counts[0] = samples.len();
+        return;
+    }
+    if samples.len() <= RADIX_CUTOFF {
+        samples.sort_unstable_by_key(|q| key(q) & mask);
+        for q in samples.iter() {
+            counts[(key(q) & mask) as usize] += 1;
+        }
+        return;
+    }
+
+    let digit_bits = std::cmp::min(bits, RADIX_BITS);
+    let shift = bits - digit_bits;
+    let digit = |q: &Sample<N>| ((key(q) & mask) >> shift) as usize;
+    let num_digits = 1 << digit_bits;
 
-    oracle_samples.sort_unstable_by_key(|s| query_bits_range(s, bitrange.clone()));
+    let histogram = if samples.len() >= PARALLEL_SCATTER_CUTOFF {
+        parallel_scatter(samples, &digit, num_digits)
+    } else {
+        flag_sort(samples, &digit, num_digits)
+    };
 
-    // create iterator to zip
-    // preallocate because collect() will try to reserve()
-    // this leads to power-of-two allocs.
-    let mut result = Vec::with_capacity(maxj);
-    (0..maxj)
+    // recurse on the buckets
+    let mut buckets = Vec::with_capacity(num_digits);
+    let mut remainder = samples;
+    for count in histogram {
+        let (bucket, rest) = remainder.split_at_mut(count);
+        buckets.push(bucket);
+        remainder = rest;
+    }
+    buckets
         .into_par_iter()
-        .map(|item| {
-            oracle_samples.partition_point(|q| query_bits_range(q, bitrange.clone()) <= item as u64)
+        .zip(counts.par_chunks_mut(1 << shift))
+        .for_each(|(bucket, counts)| radix_partition_rec(bucket, key, shift, counts));
+}
+
+/// Move the samples to their bucket with American flag sort, returns the size of every bucket
+fn flag_sort<D, const N: usize>(
+    samples: &mut [Sample<N>],
+    digit: &D,
+    num_digits: usize,
+) -> Vec<usize>
+where
+    D: Fn(&Sample<N>) -> usize,
+{
+    let mut histogram = vec![0usize; num_digits];
+    samples.iter().for_each(|q| histogram[digit(q)] += 1);
+
+    let mut heads = Vec::with_capacity(num_digits);
+    let mut tails = Vec::with_capacity(num_digits);
+    let mut end = 0;
+    for count in &histogram {
+        heads.push(end);
+        end += count;
+        tails.push(end);
+    }
+    flag_permute(samples, digit, &mut heads, &tails);
+    histogram
+}
+
+/// Move every sample in `heads[d]..tails[d]` to the head of its bucket
+///
+/// The ranges must hold exactly the samples of their bucket that are not yet in place.
+fn flag_permute<D, const N: usize>(
+    samples: &mut [Sample<N>],
+    digit: &D,
+    heads: &mut [usize],
+    tails: &[usize],
+) where
+    D: Fn(&Sample<N>) -> usize,
+{
+    for d in 0..heads.len() {
+        while heads[d] < tails[d] {
+            let mut current = digit(&samples[heads[d]]);
+            while current != d {
+                let destination = heads[current];
+                heads[current] += 1;
+                samples.swap(heads[d], destination);
+                current = digit(&samples[heads[d]]);
+            }
+            heads[d] += 1;
+        }
+    }
+}
+
+/// Pointer to the samples that the threads of [`parallel_scatter`] permute
+struct SharedSamples<const N: usize>(*mut Sample<N>);
+unsafe impl<const N: usize> Send for SharedSamples<N> {}
+unsafe impl<const N: usize> Sync for SharedSamples<N> {}
+
+/// Move the samples to their bucket in parallel and in place, returns the size of every bucket
+///
+/// Follows PARADIS: every round splits what is left of every bucket into a stripe per thread,
+/// and every thread moves the samples between its own stripes as in American flag sort.
+/// A sample whose stripe is full is left at the end of the stripe it is in.
+/// The placed samples are then moved to the front of their bucket,
+/// and the next round partitions the rest, until [`flag_permute`] finishes the last few.
+fn parallel_scatter<D, const N: usize>(
+    samples: &mut [Sample<N>],
+    digit: &D,
+    num_digits: usize,
+) -> Vec<usize>
+where
+    D: Fn(&Sample<N>) -> usize + Sync,
+{
+    let histogram = samples
+        .par_chunks(SCATTER_CHUNK)
+        .map(|chunk| {
+            let mut histogram = vec![0usize; num_digits];
+            chunk.iter().for_each(|q| histogram[digit(q)] += 1);
+            histogram
         })
-        .collect_into_vec(&mut result);
-    result.shrink_to_fit();
-    result
+        .reduce(
+            || vec![0usize; num_digits],
+            |mut histogram, chunk_histogram| {
+                histogram
+                    .iter_mut()
+                    .zip(chunk_histogram)
+                    .for_each(|(total, count)| *total += count);
+                histogram
+            },
+        );
+
+    let mut heads = Vec::with_capacity(num_digits);
+    let mut tails = Vec::with_capacity(num_digits);
+    let mut end = 0;
+    for count in &histogram {
+        heads.push(end);
+        end += count;
+        tails.push(end);
+    }
+
+    let threads = rayon::current_num_threads();
+    let mut remaining = samples.len();
+    while threads > 1 && remaining >= PARALLEL_SCATTER_CUTOFF {
+        let stripes = (0..threads)
+            .map(|thread| {
+                heads
+                    .iter()
+                    .zip(&tails)
+                    .map(|(&head, &tail)| {
+                        let len = tail - head;
+                        (
+                            head + len * thread / threads,
+                            head + len * (thread + 1) / threads,
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let shared = SharedSamples(samples.as_mut_ptr());
+        let shared = &shared;
+        let placed = stripes
+            .par_iter()
+            .map(|stripes| {
+                let mut heads = stripes.iter().map(|stripe| stripe.0).collect::<Vec<_>>();
+                let mut tails = stripes.iter().map(|stripe| stripe.1).collect::<Vec<_>>();
+                // Safe: the stripes of the threads are disjoint,
+                // and every thread only swaps samples within its own stripes.
+                let sample = |i: usize| unsafe { shared.0.add(i) };
+                for d in 0..num_digits {
+                    while heads[d] < tails[d] {
+                        let current = digit(unsafe { &*sample(heads[d]) });
+                        if current == d {
+                            heads[d] += 1;
+                        } else if heads[current] < tails[current] {
+                            unsafe { std::ptr::swap(sample(heads[d]), sample(heads[current])) };
+                            heads[current] += 1;
+                        } else {
+                            tails[d] -= 1;
+                            unsafe { std::ptr::swap(sample(heads[d]), sample(tails[d])) };
+                        }
+                    }
+                }
+                heads
+            })
+            .collect::<Vec<_>>();
+
+        // move the placed samples of every bucket to its front
+        let mut buckets = Vec::with_capacity(num_digits);
+        let mut remainder = &mut samples[..];
+        let mut offset = 0;
+        for (&head, &tail) in heads.iter().zip(&tails) {
+            let (_, rest) = remainder.split_at_mut(head - offset);
+            let (bucket, rest) = rest.split_at_mut(tail - head);
+            buckets.push(bucket);
+            remainder = rest;
+            offset = tail;
+        }
+        buckets
+            .into_par_iter()
+            .zip(heads.par_iter_mut())
+            .enumerate()
+            .for_each(|(d, (bucket, head))| {
+                let mut front = 0;
+                for (stripes, placed) in stripes.iter().zip(&placed) {
+                    for i in stripes[d].0..placed[d] {
+                        bucket.swap(front, i - *head);
+                        front += 1;
+                    }
+                }
+                *head += front;
+            });
+
+        let before = remaining;
+        remaining = heads
+            .iter()
+            .zip(&tails)
+            .map(|(head, tail)| tail - head)
+            .sum();
+        log::trace!(
+            "Parallel scatter round placed {} samples",
+            before - remaining
+        );
+        // leave it to flag_permute once a round places less than half of the samples
+        if 2 * remaining > before {
+            break;
+        }
+    }
+    flag_permute(samples, digit, &mut heads, &tails);
+    histogram
 }
 
 /// XXX get rid of this allow?
-type Slicer<'data> = fn(((usize, usize), &'data [Sample])) -> Option<&'data mut [Sample]>;
-type PartitionIterator<'data, 'pivots> = FilterMap<
+type Slicer<'data, const N: usize> =
+    fn(((usize, usize), &'data [Sample<N>])) -> Option<&'data mut [Sample<N>]>;
+type PartitionIterator<'data, 'pivots, const N: usize> = FilterMap<
     Zip<
         Zip<
             Chain<Once<usize>, rayon::iter::Copied<rayon::slice::Iter<'pivots, usize>>>,
             Chain<rayon::iter::Copied<rayon::slice::Iter<'pivots, usize>>, Once<usize>>,
         >,
-        RepeatN<&'data [Sample]>,
+        RepeatN<&'data [Sample<N>]>,
     >,
-    Slicer<'data>,
+    Slicer<'data, N>,
 >;
 #[allow(mutable_transmutes)]
-pub(crate) fn create_partitions<'data, 'pivots>(
-    oracle_samples: &'data mut [Sample],
+pub(crate) fn create_partitions<'data, 'pivots, const N: usize>(
+    oracle_samples: &'data mut [Sample<N>],
     pivots: &'pivots Vec<usize>,
-) -> PartitionIterator<'data, 'pivots> {
+) -> PartitionIterator<'data, 'pivots, N> {
     let num_samples = oracle_samples.len();
     let num_pivots = pivots.len();
     let lefts = rayon::iter::once(0).chain(pivots.par_iter().copied());
@@ -68,13 +325,14 @@ pub(crate) fn create_partitions<'data, 'pivots>(
         .chain(rayon::iter::once(num_samples));
     let oracle_samples = &*oracle_samples;
     let refs = rayon::iter::repeatn(oracle_samples, num_pivots + 1);
-    let func: Slicer = |((l, r), oracle_samples)| unsafe {
+    let func: Slicer<N> = |((l, r), oracle_samples)| unsafe {
         if l == r {
             None
         } else {
             debug_assert!(l < r, "{} >= {}", l, r);
-            let slice =
-                std::mem::transmute::<&'data [Sample], &'data mut [Sample]>(&oracle_samples[l..r]);
+            let slice = std::mem::transmute::<&'data [Sample<N>], &'data mut [Sample<N>]>(
+                &oracle_samples[l..r],
+            );
             Some(slice)
         }
     };
@@ -93,9 +351,8 @@ pub fn partition_reduce(oracle: &mut LpnOracle, b: u32) {
     bkw_reduce(oracle, 2, b);
 }
 
-fn bkw_reduce_inplace(oracle: &mut LpnOracle, i: usize, b: usize) {
-    let num_samples = oracle.samples.len();
-    let k = oracle.get_k() as usize;
+fn bkw_reduce_inplace<const N: usize>(samples: &mut Vec<Sample<N>>, k: usize, i: usize, b: usize) {
+    let num_samples = samples.len();
 
     let maxj = 2usize.pow(b as u32);
     // max j:
@@ -108,7 +365,7 @@ fn bkw_reduce_inplace(oracle: &mut LpnOracle, i: usize, b: usize) {
 
     let bitrange: ops::Range<usize> = (k - (b * i))..(k - (b * (i - 1)));
     // first collect "firsts" so we can do the later part in parallel
-    for (j, q) in oracle.samples.iter_mut().enumerate().skip(1) {
+    for (j, q) in samples.iter_mut().enumerate().skip(1) {
         let idx = query_bits_range(&q, bitrange.clone()) as usize;
         if firsts_idxs[idx].is_some() {
             if firsts_idxs.iter().all(|item| item.is_some()) {
@@ -128,12 +385,12 @@ fn bkw_reduce_inplace(oracle: &mut LpnOracle, i: usize, b: usize) {
         .for_each(|idx| {
             // safe as we've excluded the None values
             let idx = unsafe { idx.unchecked_unwrap() }.get();
-            let item = oracle.samples.swap_remove(idx);
+            let item = samples.swap_remove(idx);
             let idx = query_bits_range(&item, bitrange.clone()) as usize;
             firsts[idx] = Some(item);
         });
     // not consuming the iterator to do as much as possible in-place.
-    oracle.samples.par_iter_mut().for_each(|q| {
+    samples.par_iter_mut().for_each(|q| {
         let idx = query_bits_range(&q, bitrange.clone()) as usize;
         if let Some(item) = &firsts[idx] {
             q.xor_into(item);
@@ -141,32 +398,26 @@ fn bkw_reduce_inplace(oracle: &mut LpnOracle, i: usize, b: usize) {
     });
 }
 
-fn bkw_reduce_sorted(oracle: &mut LpnOracle, i: usize, b: usize) {
-    let k = oracle.get_k();
+fn bkw_reduce_sorted<const N: usize>(samples: &mut Vec<Sample<N>>, k: usize, i: usize, b: usize) {
     let bitrange: ops::Range<usize> = (k - (b * i))..(k - (b * (i - 1)));
 
     let maxj = 2usize.pow(b as u32);
     // max j:
     println!(
         "BKW iteration, {} samples left, expecting to remove {} through sorting method",
-        oracle.samples.len(),
+        samples.len(),
         maxj
     );
 
-    oracle.samples.par_sort_unstable_by_key(|q| {
-        let key = query_bits_range(q, bitrange.clone());
-        key
-    });
-
     // split into partitions
-    let oracle_start = oracle.samples.as_ptr() as usize;
+    let oracle_start = samples.as_ptr() as usize;
     log::debug!("Creating pivots");
-    let pivots = create_pivots(&mut oracle.samples, &bitrange);
-    let partitions: PartitionIterator = create_partitions(&mut oracle.samples, &pivots);
+    let pivots = create_pivots(samples, &bitrange);
+    let partitions: PartitionIterator<N> = create_partitions(samples, &pivots);
 
     // process produced slices
     let partitions = partitions
-        .map(|partition: &mut [Sample]| {
+        .map(|partition: &mut [Sample<N>]| {
             let (partition, remainder) = partition.split_at_mut(1);
             let first = &partition[0];
             let len = remainder.len();
@@ -184,16 +435,16 @@ fn bkw_reduce_sorted(oracle: &mut LpnOracle, i: usize, b: usize) {
     // compute indexes of firsts
     let firsts = partitions
         .into_iter()
-        .map(|partition| (partition - oracle_start) / std::mem::size_of::<Sample>());
+        .map(|partition| (partition - oracle_start) / std::mem::size_of::<Sample<N>>());
 
-    // this is descending because par_iter_map preserves order.
-    for index in firsts {
-        oracle.samples.swap_remove(index);
+    // remove in descending order so the indices stay valid, par_iter_map preserves order.
+    for index in firsts.rev() {
+        samples.swap_remove(index);
     }
 }
 
 /// Performs the BKW reduction algorithm, see [`partition_reduce`] for public usage
-fn bkw_reduce(oracle: &mut LpnOracle, a: u32, b: u32) {
+pub(crate) fn bkw_reduce(oracle: &mut LpnOracle, a: u32, b: u32) {
     let k = oracle.get_k();
     let a = a as usize;
     let b = b as usize;
@@ -202,11 +453,13 @@ fn bkw_reduce(oracle: &mut LpnOracle, a: u32, b: u32) {
     for i in 1..a {
         // somewhat empirically decided through benchmark
         // probably related to size of LUT fitting in cache
-        if b < 10 {
-            bkw_reduce_inplace(oracle, i, b);
-        } else {
-            bkw_reduce_sorted(oracle, i, b)
-        }
+        with_samples!(&mut oracle.samples, samples => {
+            if b < 10 {
+                bkw_reduce_inplace(samples, k, i, b);
+            } else {
+                bkw_reduce_sorted(samples, k, i, b)
+            }
+        });
     }
 
     // Set the new k
@@ -227,11 +480,11 @@ pub fn majority(oracle: LpnOracle) -> BinVector {
         "Selecting all samples with hw=1 from {} samples",
         oracle.samples.len()
     );
-    let samples = oracle
-        .samples
+    let samples = with_samples!(oracle.samples, samples => samples
         .into_iter()
-        .filter_map(|q| if q.count_ones() == 1 { Some(q) } else { None })
-        .collect::<Vec<Sample>>();
+        .filter(|q| q.count_ones() == 1)
+        .map(|q| (q.get_block(0), q.get_product()))
+        .collect::<Vec<_>>());
 
     // allocate smaller vec
     let mut count_sum: FnvHashMap<StorageBlock, (u64, u64)> =
@@ -241,11 +494,11 @@ pub fn majority(oracle: LpnOracle) -> BinVector {
         "Sorting out and counting {} samples for majority selection",
         samples.len()
     );
-    for query in samples.into_iter() {
+    for (query, product) in samples.into_iter() {
         debug_assert_eq!(query.count_ones(), 1);
-        let count_sum = count_sum.entry(query.get_block(0)).or_insert((0, 0));
+        let count_sum = count_sum.entry(query).or_insert((0, 0));
         count_sum.0 += 1;
-        if query.get_product() {
+        if product {
             count_sum.1 += 1;
         }
     }
@@ -283,14 +536,69 @@ mod test {
         assert_eq!(solution, secret);
     }
 
+    #[test]
+    fn parallel_scatter_in_place() {
+        let mut oracle = LpnOracle::new(40, 1.0 / 8.0);
+        oracle.get_samples(200_000);
+        let mut samples = oracle.samples.to_vec();
+        let mut expected = samples.clone();
+        let digit = |q: &Sample| query_bits_range(q, 32..40) as usize;
+        expected.sort_unstable();
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap();
+        let histogram = pool.install(|| parallel_scatter(&mut samples, &digit, 1 << 8));
+        let mut start = 0;
+        for (d, &count) in histogram.iter().enumerate() {
+            assert!(samples[start..start + count].iter().all(|q| digit(q) == d));
+            start += count;
+        }
+        assert_eq!(start, samples.len());
+        samples.sort_unstable();
+        assert_eq!(samples, expected);
+    }
+
+    #[test]
+    fn radix_matches_sort() {
+        for &(b, n) in &[(3, 50), (10, 10_000), (19, 100_000), (12, 300_000)] {
+            let mut oracle = LpnOracle::new(40, 1.0 / 8.0);
+            oracle.get_samples(n);
+            let bitrange = 40 - b..40;
+            let mut samples = oracle.samples.to_vec();
+            let mut expected = samples.clone();
+            expected.sort_unstable_by_key(|q| query_bits_range(q, bitrange.clone()));
+
+            let pivots = create_pivots(&mut samples, &bitrange);
+            assert_eq!(pivots.len(), 1 << b);
+            assert_eq!(*pivots.last().unwrap(), n);
+            let keys = |samples: &[Sample]| {
+                samples
+                    .iter()
+                    .map(|q| query_bits_range(q, bitrange.clone()))
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(keys(&samples), keys(&expected));
+            for (window, &pivot) in pivots.iter().enumerate() {
+                assert_eq!(
+                    pivot,
+                    expected.partition_point(|q| query_bits_range(q, bitrange.clone())
+                        <= window as u64)
+                );
+            }
+        }
+    }
+
     #[test]
     fn test_partition() {
         let k = MAX_K - 10;
         let mut oracle = LpnOracle::new(k as u32, 1.0 / 8.0);
         oracle.get_samples(1000);
         let bitrange = k - 10..k;
-        let pivots = create_pivots(&mut oracle.samples, &bitrange);
-        let parts: PartitionIterator = create_partitions(&mut oracle.samples, &pivots);
+        let mut samples = oracle.samples.to_vec();
+        let pivots = create_pivots(&mut samples, &bitrange);
+        let parts: PartitionIterator<SAMPLE_LEN> = create_partitions(&mut samples, &pivots);
         let mut failed = false;
         for part in parts.collect::<Vec<_>>() {
             let first_range = query_bits_range(&part[0], bitrange.clone());
@@ -0,0 +1,129 @@
+//! The coded-BKW reduction by Guo, Johansson and Stankovski
+//!
+//! Instead of merging samples that agree exactly on a window of `b` bits,
+//! we decode a window of `code.length()` bits to the nearest codeword
+//! and merge samples that decode to the same codeword.
+//! The window of the merged sample is the sum of the two decoding errors,
+//! which we absorb into the noise.
+use crate::{
+    bkw::{create_partitions, create_pivots},
+    codes::BinaryCode,
+    oracle::{query_bits_range, with_samples, LpnOracle, Sample},
+    util::log_2,
+};
+use m4ri_rust::friendly::BinVector;
+use rayon::prelude::*;
+use std::ops;
+
+/// Coded-BKW reduction on the last `code.length()` bits
+///
+/// The samples in every partition of samples that decode to the same message
+/// are added to the first sample of the partition, which is then removed.
+/// The removed window `e + e'` adds `<e + e', s>` to the noise.
+///
+/// `$k' = k - length(G)$`
+/// `$n' = n - 2^{dim(G)}$`
+/// `$d' = d^2 * bc^2$`
+pub fn coded_bkw_reduce<T: BinaryCode + Sync>(oracle: &mut LpnOracle, code: &T) {
+    assert!(
+        oracle.delta_s > 0.0,
+        "This reduction only works for sparse secrets!"
+    );
+    let k = oracle.get_k();
+    let length = code.length();
+    let dimension = code.dimension();
+    assert!(
+        length < k,
+        "The code should be shorter than the problem size"
+    );
+    assert!(dimension < 64, "Messages should fit in a u64");
+
+    log::info!(
+        "Coded-BKW iteration with a [{}, {}] code, {} samples (2^{})",
+        length,
+        dimension,
+        oracle.samples.len(),
+        log_2(oracle.samples.len())
+    );
+
+    let window_start = k - length;
+    with_samples!(&mut oracle.samples, samples => decode_and_merge(samples, code, window_start));
+
+    oracle.truncate(window_start);
+    let bias = code.bias(oracle.delta_s);
+    oracle.delta = oracle.delta.powi(2) * bias * bias;
+    log::debug!(
+        "Coded-BKW iteration done, {} samples (2^{}) left, k' = {}, new delta = {}",
+        oracle.samples.len(),
+        log_2(oracle.samples.len()),
+        oracle.get_k(),
+        oracle.delta
+    );
+}
+
+/// Replace the window starting at `window_start` by its decoded message and merge the samples
+/// that decode to the same message
+fn decode_and_merge<T: BinaryCode + Sync, const N: usize>(
+    samples: &mut Vec<Sample<N>>,
+    code: &T,
+    window_start: usize,
+) {
+    let length = code.length();
+    let dimension = code.dimension();
+
+    // replace the window by the decoded message, we drop the window later anyway
+    log::debug!("Decoding windows");
+    samples.par_iter_mut().for_each(|sample| {
+        let window = BinVector::from_function(length, |i| sample.get_bit(window_start + i));
+        let message = code.decode_to_message(&window).unwrap();
+        for i in 0..length {
+            sample.set_bit(window_start + i, i < dimension && message.get(i).unwrap());
+        }
+    });
+
+    // partition by message and merge with the first sample of each partition
+    let bitrange: ops::Range<usize> = window_start..(window_start + dimension);
+    log::debug!("Creating pivots");
+    let pivots = create_pivots(samples, &bitrange);
+    let firsts = create_partitions(samples, &pivots)
+        .map(|partition: &mut [Sample<N>]| {
+            let (first, remainder) = partition.split_at_mut(1);
+            let first = &first[0];
+            remainder.iter_mut().for_each(|q| {
+                q.xor_into(first);
+                debug_assert_eq!(0, query_bits_range(q, bitrange.clone()));
+            });
+            first as *const Sample<N> as usize
+        })
+        .collect::<Vec<_>>();
+
+    // remove the firsts, in descending order so the indices stay valid
+    let oracle_start = samples.as_ptr() as usize;
+    for first in firsts.into_iter().rev() {
+        samples.swap_remove((first - oracle_start) / std::mem::size_of::<Sample<N>>());
+    }
+}
+
+#[cfg(all(test, feature = "hamming"))]
+mod test {
+    use super::*;
+    use crate::{codes::HammingCode7_4, lf1::fwht_solve};
+
+    #[test]
+    fn run_coded_bkw() {
+        let mut secret = BinVector::from_elem(20, false);
+        secret.set(2, true);
+        secret.set(9, true);
+        secret.set(17, true);
+        let secret = Sample::from_binvector(&secret, false);
+        let mut oracle = LpnOracle::new_with_secret(secret, 20, 1.0 / 16.0);
+        oracle.delta_s = 0.75;
+        oracle.get_samples(100_000);
+        let secret = oracle.secret.clone();
+
+        coded_bkw_reduce(&mut oracle, &HammingCode7_4);
+        assert_eq!(oracle.get_k(), 13);
+        assert!(oracle.delta < 0.875f64.powi(2));
+        assert_eq!(fwht_solve(oracle), secret.as_binvector(13));
+    }
+}
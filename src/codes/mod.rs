@@ -79,6 +79,15 @@ pub trait BinaryCode {
         }
     }
 
+    /// Decode a sample like [`decode_sample`](BinaryCode::decode_sample)
+    /// and return the weight of the decoding error
+    fn decode_sample_with_distance(&self, c: &mut Sample) -> u32 {
+        let original = c.as_binvector(self.length());
+        self.decode_sample(c);
+        let codeword = self.encode(&c.as_binvector(self.dimension()));
+        (&original + &codeword).count_ones()
+    }
+
     fn decode_slice(&self, c: &mut [u64]) {
         let mut v = BinVector::with_capacity(self.length());
         let stor = unsafe { v.get_storage_mut() };
@@ -93,6 +102,16 @@ pub trait BinaryCode {
 
     /// Get or compute the bc of a code
     fn bias(&self, delta: f64) -> f64 {
+        self.distance_distribution()
+            .into_iter()
+            .enumerate()
+            .fold(0f64, |acc, (dist, p)| acc + p * delta.powi(dist as i32))
+    }
+
+    /// Estimate the distribution of the distance of a random vector to the nearest codeword
+    ///
+    /// Element `d` is the probability of distance `d`. Empty if decoding failed.
+    fn distance_distribution(&self) -> Vec<f64> {
         let mut distances = Vec::with_capacity(N);
         if 2f64.powi(self.length() as i32) > 1.5 * N as f64 {
             let mut seen = HashSet::with_capacity(N);
@@ -107,7 +126,7 @@ pub trait BinaryCode {
                     seen.insert(v);
                 } else {
                     println!("Decoding something failed");
-                    return 0.0;
+                    return Vec::new();
                 }
             }
         } else {
@@ -118,17 +137,17 @@ pub trait BinaryCode {
                     distances.push((&v + &decoded).count_ones() as i32);
                 } else {
                     println!("Decoding something failed");
-                    return 0.0;
+                    return Vec::new();
                 }
             }
         }
 
         let count = distances.len();
-        let sum = distances
-            .into_iter()
-            .fold(0f64, |acc, dist| acc + delta.powi(dist));
-
-        sum / (count as f64)
+        let mut distribution = vec![0f64; self.length() + 1];
+        for dist in distances {
+            distribution[dist as usize] += 1.0 / (count as f64);
+        }
+        distribution
     }
 }
 
@@ -0,0 +1,261 @@
+//! Reductions that remove coordinates from the samples: drop, trunc and known secret bits
+//!
+//! Unlike [`LpnOracle::get_samples_drop`], these work on the samples the oracle already holds
+//! and allow arbitrary positions. They are recorded in the history of the oracle,
+//! see [`reassemble_secret`].
+use crate::{
+    oracle::{with_samples, LpnOracle, Reduction},
+    util::log_2,
+};
+use m4ri_rust::friendly::BinVector;
+use rayon::prelude::*;
+
+/// Drop reduction
+///
+/// Only keeps the samples that are zero on `positions` and removes those coordinates.
+///
+/// `$k' = k - t$`
+/// `$n' = n / 2^t$`
+/// `$\delta' = \delta$`
+pub fn drop_reduce(oracle: &mut LpnOracle, positions: &[usize]) {
+    let positions = sorted_positions(oracle, positions);
+    log::info!(
+        "Drop reduction on {} positions, {} samples (2^{})",
+        positions.len(),
+        oracle.samples.len(),
+        log_2(oracle.samples.len())
+    );
+    with_samples!(&mut oracle.samples, samples => {
+        samples.retain(|sample| positions.iter().all(|&pos| !sample.get_bit(pos)))
+    });
+    remove_positions(oracle, &positions);
+    oracle.history.push(Reduction::Drop(positions));
+    log::debug!(
+        "Drop reduction done, {} samples (2^{}) left",
+        oracle.samples.len(),
+        log_2(oracle.samples.len())
+    );
+}
+
+/// Trunc reduction
+///
+/// Removes the coordinates at `positions` from all samples,
+/// which adds their part of the inner product to the noise.
+/// Only useful if the secret is sparse.
+///
+/// `$k' = k - t$`
+/// `$n' = n$`
+/// `$\delta' = \delta ((1 + \delta_s) / 2)^t$`
+pub fn trunc_reduce(oracle: &mut LpnOracle, positions: &[usize]) {
+    let positions = sorted_positions(oracle, positions);
+    if oracle.delta_s <= 0.0 {
+        log::warn!("Trunc reduction on a non-sparse secret destroys the bias");
+    }
+    log::info!("Trunc reduction on {} positions", positions.len());
+    remove_positions(oracle, &positions);
+    oracle.delta *= ((1.0 + oracle.delta_s) / 2.0).powi(positions.len() as i32);
+    oracle.history.push(Reduction::Trunc(positions));
+}
+
+/// Known secret bits reduction
+///
+/// Takes `(position, value)` pairs of secret bits we already know, for example from leakage.
+/// Adds `a_i s_i` to the product of every sample and removes the coordinates.
+///
+/// `$k' = k - t$`
+/// `$n' = n$`
+/// `$\delta' = \delta$`
+pub fn known_bits_reduce(oracle: &mut LpnOracle, bits: &[(usize, bool)]) {
+    let positions = sorted_positions(
+        oracle,
+        &bits.iter().map(|&(pos, _)| pos).collect::<Vec<_>>(),
+    );
+    assert_eq!(positions.len(), bits.len(), "Positions should be distinct");
+    let mut bits = bits.to_vec();
+    bits.sort_unstable();
+    let ones = bits
+        .iter()
+        .filter(|(_, value)| *value)
+        .map(|&(pos, _)| pos)
+        .collect::<Vec<_>>();
+    log::info!(
+        "Removing {} known secret bits, of which {} are set",
+        bits.len(),
+        ones.len()
+    );
+
+    with_samples!(&mut oracle.samples, samples => samples.par_iter_mut().for_each(|sample| {
+        let flip = ones.iter().filter(|&&pos| sample.get_bit(pos)).count() % 2 == 1;
+        if flip {
+            sample.set_product(!sample.get_product());
+        }
+    }));
+    remove_positions(oracle, &positions);
+    oracle.history.push(Reduction::KnownBits(bits));
+}
+
+/// Reassemble the secret of the original problem from the `secret` of the reduced problem
+///
+/// Undoes the reductions in the history of the oracle by inserting the removed coordinates.
+/// The bits removed by the drop and trunc reductions are unknown and set to zero.
+/// `secret` should have the length the problem had after the last of these reductions.
+pub fn reassemble_secret(oracle: &LpnOracle, secret: &BinVector) -> BinVector {
+    oracle
+        .history
+        .iter()
+        .rev()
+        .fold(secret.clone(), |secret, reduction| {
+            let (positions, values) = match reduction {
+                Reduction::Drop(positions) | Reduction::Trunc(positions) => {
+                    log::warn!(
+                        "The secret bits at {:?} were removed by a reduction and are unknown",
+                        positions
+                    );
+                    (positions.clone(), vec![false; positions.len()])
+                }
+                Reduction::KnownBits(bits) => bits.iter().copied().unzip(),
+            };
+            let mut removed = positions.iter().zip(values).peekable();
+            let mut remaining = secret.iter();
+            BinVector::from_bools(
+                &(0..(secret.len() + positions.len()))
+                    .map(|i| match removed.peek() {
+                        Some(&(&pos, value)) if pos == i => {
+                            removed.next();
+                            value
+                        }
+                        _ => remaining.next().unwrap(),
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        })
+}
+
+/// The position in the original problem of every coordinate of the reduced problem
+///
+/// Follows the reductions in the history of the oracle, like [`reassemble_secret`].
+pub fn original_positions(oracle: &LpnOracle) -> Vec<usize> {
+    oracle.history.iter().rev().fold(
+        (0..oracle.get_k()).collect::<Vec<_>>(),
+        |positions, reduction| {
+            let removed = match reduction {
+                Reduction::Drop(positions) | Reduction::Trunc(positions) => positions.clone(),
+                Reduction::KnownBits(bits) => bits.iter().map(|&(pos, _)| pos).collect(),
+            };
+            positions
+                .into_iter()
+                .map(|pos| {
+                    removed.iter().fold(
+                        pos,
+                        |pos, &removed| if removed <= pos { pos + 1 } else { pos },
+                    )
+                })
+                .collect()
+        },
+    )
+}
+
+/// Check the positions and return them sorted
+fn sorted_positions(oracle: &LpnOracle, positions: &[usize]) -> Vec<usize> {
+    let k = oracle.get_k();
+    let mut positions = positions.to_vec();
+    positions.sort_unstable();
+    positions.dedup();
+    assert!(
+        positions.iter().all(|&pos| pos < k),
+        "Positions should be smaller than k = {}",
+        k
+    );
+    assert!(positions.len() < k, "Can't remove all coordinates");
+    positions
+}
+
+/// Remove the coordinates from the samples and the secret
+fn remove_positions(oracle: &mut LpnOracle, positions: &[usize]) {
+    let k = oracle.get_k();
+    with_samples!(&mut oracle.samples, samples => {
+        samples
+            .par_iter_mut()
+            .for_each(|sample| sample.remove_bits(positions))
+    });
+    oracle.secret.remove_bits(positions);
+    unsafe { oracle.set_k(k - positions.len()) };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{lf1::fwht_solve, oracle::Sample};
+
+    #[test]
+    fn drop_then_fwht() {
+        let mut oracle = LpnOracle::new(20, 1.0 / 8.0);
+        oracle.get_samples(100_000);
+        let secret = oracle.secret.as_binvector(20);
+        drop_reduce(&mut oracle, &[0, 7, 19, 3]);
+        assert_eq!(oracle.get_k(), 16);
+
+        let expected = BinVector::from_bools(
+            &(0..20)
+                .filter(|i| ![0, 3, 7, 19].contains(i))
+                .map(|i| secret.get(i).unwrap())
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(oracle.secret.as_binvector(16), expected);
+        assert_eq!(oracle.history(), &[Reduction::Drop(vec![0, 3, 7, 19])]);
+        let positions = original_positions(&oracle);
+        assert_eq!(positions[..4], [1, 2, 4, 5]);
+        assert_eq!(positions[15], 18);
+
+        let solution = fwht_solve(oracle.clone());
+        assert_eq!(solution, expected);
+        let mut reassembled = secret;
+        [0, 3, 7, 19]
+            .iter()
+            .for_each(|&pos| reassembled.set(pos, false));
+        assert_eq!(reassemble_secret(&oracle, &solution), reassembled);
+    }
+
+    #[test]
+    fn trunc_then_fwht() {
+        let mut secret = BinVector::from_elem(20, false);
+        secret.set(2, true);
+        secret.set(11, true);
+        let secret = Sample::from_binvector(&secret, false);
+        let mut oracle = LpnOracle::new_with_secret(secret, 20, 1.0 / 8.0);
+        oracle.delta_s = 0.75;
+        oracle.get_samples(20_000);
+        trunc_reduce(&mut oracle, &[1, 5, 12]);
+        assert_eq!(oracle.get_k(), 17);
+        known_bits_reduce(&mut oracle, &[(0, false)]);
+        assert_eq!(
+            original_positions(&oracle)[..4],
+            [2, 3, 4, 6],
+            "Positions should be mapped through both reductions"
+        );
+        assert!((oracle.delta - 0.75 * 0.875f64.powi(3)).abs() < 1e-10);
+
+        let mut expected = BinVector::from_elem(16, false);
+        expected.set(0, true);
+        expected.set(8, true);
+        assert_eq!(fwht_solve(oracle), expected);
+    }
+
+    #[test]
+    fn known_bits_then_reassemble() {
+        let mut oracle = LpnOracle::new(24, 1.0 / 8.0);
+        oracle.get_samples(100_000);
+        let secret = oracle.secret.as_binvector(24);
+        let known = [5, 0, 22, 13]
+            .iter()
+            .map(|&pos| (pos, secret.get(pos).unwrap()))
+            .collect::<Vec<_>>();
+        known_bits_reduce(&mut oracle, &known);
+        assert_eq!(oracle.get_k(), 20);
+        assert_eq!(oracle.history().len(), 1);
+
+        let solution = fwht_solve(oracle.clone());
+        assert_eq!(solution, oracle.secret.as_binvector(20));
+        assert_eq!(reassemble_secret(&oracle, &solution), secret);
+    }
+}
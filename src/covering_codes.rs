@@ -1,9 +1,14 @@
 //! Implements the covering codes reduction and sparse secret transformation
+//!
+//! All covering-code reductions multiply `oracle.delta` by the bias the code introduces,
+//! `bc` for the code, or the bias of the kept samples for [`code_reduce_filtered`].
 use std::sync::{Arc, Mutex};
 
 use crate::{
-    oracle::{LpnOracle, Sample},
+    lf1::log_likelihood_weight,
+    oracle::{with_samples, LpnOracle, Sample},
     random::lpn_thread_rng,
+    stream::SampleStream,
 };
 use indicatif::ProgressBar;
 use m4ri_rust::friendly::BinMatrix;
@@ -11,7 +16,9 @@ use m4ri_rust::friendly::BinVector;
 use rayon::prelude::*;
 
 use crate::codes::BinaryCode;
-use rand::prelude::*;
+
+/// The number of samples per matrix product in [`sparse_secret_reduce`]
+const SPARSE_CHUNK_SIZE: usize = 1 << 12;
 
 /// Sparse secret reduction
 ///
@@ -23,51 +30,39 @@ use rand::prelude::*;
 /// `$d'_s = d$`
 pub fn sparse_secret_reduce(oracle: &mut LpnOracle) {
     let k = oracle.get_k();
+    let n = oracle.samples.len();
+    assert!(n > k, "Need more than k samples");
     let mut rng = lpn_thread_rng();
 
-    // cheat by picking from the first million
-    let searchspace = std::cmp::min(oracle.samples.len(), 1_000_000);
-
-    // get M, e, c'
-    let (m, c_prime, samples) = loop {
-        let (a, b, samples) = {
-            let samples: Vec<_> = oracle.samples[..searchspace]
-                .choose_multiple(&mut rng, k)
-                .cloned()
-                .collect();
-            // replace by matrix directly?
-            let mut b = BinVector::with_capacity(k);
-            //let mut e = BinVector::with_capacity(k);
-            (
-                // vectors on the columns
-                BinMatrix::new(
-                    samples
-                        .iter()
-                        .map(|q| {
-                            b.push(q.get_product());
-                            //e.push(q.e);
-                            q.as_binvector(k)
-                        })
-                        .collect(),
-                ),
-                b,
-                //e,
-                samples,
-            )
-        };
-        if a.clone().echelonize() == k {
-            break (a, b, samples);
+    // get M, c' from k random samples that are linearly independent
+    let (m, c_prime, indices) = loop {
+        let indices = rand::seq::index::sample(&mut rng, n, k).into_vec();
+        let samples = with_samples!(&oracle.samples, samples => indices
+            .iter()
+            .map(|&idx| samples[idx].resize())
+            .collect::<Vec<Sample>>());
+        // vectors on the rows
+        let m = BinMatrix::from_slices(
+            &samples.iter().map(|q| q.get_sample()).collect::<Vec<_>>(),
+            k,
+        );
+        if m.clone().echelonize() == k {
+            let c_prime =
+                BinVector::from_bools(&samples.iter().map(|q| q.get_product()).collect::<Vec<_>>());
+            break (m, c_prime, indices);
         }
     };
 
     // update the secret:
     let original_secret = oracle.secret.as_binvector(k);
-    log::debug!(
-        "the secret prior to reduction to a sparse secret was: {:?}",
-        original_secret
-    );
+    if oracle.is_secret_known() {
+        log::debug!(
+            "the secret prior to reduction to a sparse secret was: {:?}",
+            original_secret
+        );
+    }
 
-    if oracle.delta == 1.0 {
+    if oracle.delta == 1.0 && oracle.is_secret_known() {
         debug_assert_eq!(
             (&original_secret * &m.transposed()),
             c_prime,
@@ -82,42 +77,46 @@ pub fn sparse_secret_reduce(oracle: &mut LpnOracle) {
         original_secret
     );
 
-    let m_t_inv = m.inverted();
-
     log::trace!("removing the samples we took for the transformation matrix");
-    let mut poses = samples
-        .into_par_iter()
-        .map(|sample| {
-            oracle.samples[..searchspace]
-                .iter()
-                .position(|item| item == &sample)
-                .unwrap()
+    let mut indices = indices;
+    indices.sort_unstable();
+    with_samples!(&mut oracle.samples, samples => {
+        indices.into_iter().rev().for_each(|idx| {
+            samples.swap_remove(idx);
         })
-        .collect::<Vec<_>>();
-    poses.sort_unstable();
-    poses.into_iter().rev().for_each(|pos| {
-        oracle.samples.swap_remove(pos);
     });
 
-    // update the samples
-    //let secret = &oracle.secret;
-    let m_t_inv_t = &m_t_inv.transposed();
+    // a' = a M^-1 and c' = c + <a', c'>, as one product [a | c] [M^-1 | M^-1 c'] per chunk
+    let m_inv = m.inverted();
+    let transform = m_inv.augmented(&(&m_inv * &c_prime.as_column_matrix()));
 
     let progress = ProgressBar::new(oracle.samples.len() as u64);
     log::info!("Sparse-secretifying samples");
     progress.set_draw_delta(oracle.samples.len() as u64 / 100);
     progress.reset();
     let progress = Arc::new(Mutex::new(progress));
-    oracle.samples.par_chunks_mut(10000).for_each(|queries| {
-        let len_chunk = queries.len();
-        for query in queries {
-            let new_v = m_t_inv_t.mul_slice(query.get_sample()).as_vector();
-            let new_product = query.get_product() ^ &new_v * &c_prime;
-            *query = Sample::from_binvector(&new_v, new_product);
-            //debug_assert_eq!((secret * &new_v) ^ query.e, query.c);
+    with_samples!(&mut oracle.samples, samples => samples
+    .par_chunks_mut(SPARSE_CHUNK_SIZE)
+    .for_each(|queries| {
+        let a = BinMatrix::from_slices(
+            &queries.iter().map(|q| q.get_sample()).collect::<Vec<_>>(),
+            k,
+        );
+        let result = &a * &transform;
+        for (row, query) in queries.iter_mut().enumerate() {
+            let new_product = query.get_product() ^ result.bit(row, k);
+            let blocks = query.get_sample_mut();
+            for (word, block) in blocks.iter_mut().enumerate().take(k.div_ceil(64)) {
+                // this is okay because the row has k + 1 columns
+                *block = unsafe { result.get_word_unchecked(row, word) };
+                if (word + 1) * 64 > k {
+                    *block &= (1 << (k % 64)) - 1;
+                }
+            }
+            query.set_product(new_product);
         }
-        progress.lock().unwrap().inc(len_chunk as u64);
-    });
+        progress.lock().unwrap().inc(queries.len() as u64);
+    }));
     progress.lock().unwrap().finish_and_clear();
 
     oracle.sparse_transform_matrix = Some(m);
@@ -158,28 +157,240 @@ pub fn code_reduce<T: BinaryCode + Sync>(oracle: &mut LpnOracle, code: &T) {
     progress.set_draw_delta(oracle.samples.len() as u64 / 100);
     progress.reset();
     let progress = Arc::new(Mutex::new(progress));
-    oracle.samples.par_chunks_mut(10000).for_each(|queries| {
+    with_samples!(&mut oracle.samples, samples => samples.par_chunks_mut(10000).for_each(|queries| {
         let chunk_len = queries.len();
         for query in queries {
-            code.decode_sample(query)
+            query.with_wide(|query| code.decode_sample(query))
         }
         progress.lock().unwrap().inc(chunk_len as u64);
-    });
+    }));
     progress.lock().unwrap().finish_and_clear();
 
+    transform_secret(oracle, code);
+    apply_code_bias(oracle, code);
+}
+
+/// Covering codes reduction on a stream of samples
+///
+/// Like [`code_reduce`], but decodes the samples lazily as they are produced by the stream.
+pub fn code_reduce_stream<'a, T: BinaryCode + Sync>(
+    mut stream: SampleStream<'a>,
+    code: &'a T,
+) -> SampleStream<'a> {
+    assert!(
+        stream.oracle().delta_s > 0.0,
+        "This reduction only works for sparse secrets!"
+    );
+    assert_eq!(
+        stream.oracle().get_k(),
+        code.length(),
+        "The length of the code does not match the problem size!"
+    );
+
+    log::info!("Decoding samples while streaming");
+    transform_secret(stream.oracle_mut(), code);
+    apply_code_bias(stream.oracle_mut(), code);
+    stream.map(move |query| code.decode_sample(query))
+}
+
+/// Transform the secret into the message space of the code after decoding the samples
+fn transform_secret<T: BinaryCode>(oracle: &mut LpnOracle, code: &T) {
     log::warn!(
         "Note that we transformed the secret $s$ into $s'=s*G^T$ with k' = {}!",
-        oracle.get_k()
+        code.dimension()
     );
     let k = oracle.get_k();
     let gen_t = code.generator_matrix().transposed();
     oracle.secret = Sample::from_binvector(&(&oracle.secret.as_binvector(k) * &gen_t), false);
 
     unsafe { oracle.set_k(code.dimension()) };
+}
+
+/// Multiply the bias of the problem by the bias `bc` the code introduces
+fn apply_code_bias<T: BinaryCode>(oracle: &mut LpnOracle, code: &T) {
+    log::trace!("Computing new delta");
+    oracle.delta *= code.bias(oracle.delta_s);
+    log::debug!("New delta = {}", oracle.delta);
+}
+
+/// Reduce using the covering codes attack, keeping only samples that decode well
+///
+/// Only keeps the samples that are at distance at most `max_distance` from the nearest codeword.
+/// This trades samples for bias: the kept samples have bias
+/// `$bc_w = E[d_s^{wt(e)} | wt(e) \leq w]$` instead of `$bc$`.
+///
+/// $k' = dim(G)$
+/// $n' = n * Pr[wt(e) \leq w]$
+/// $d' = d * bc_w$
+/// $d'_s$ depends on $d_s$ and $G$.
+pub fn code_reduce_filtered<T: BinaryCode + Sync>(
+    oracle: &mut LpnOracle,
+    code: &T,
+    max_distance: u32,
+) {
+    assert!(
+        oracle.delta_s > 0.0,
+        "This reduction only works for sparse secrets!"
+    );
+    assert_eq!(
+        oracle.get_k(),
+        code.length(),
+        "The length of the code does not match the problem size!"
+    );
+
+    let distribution = code.distance_distribution();
+    let kept_fraction = distribution
+        .iter()
+        .take(max_distance as usize + 1)
+        .sum::<f64>();
+    log::info!(
+        "Decoding samples, expecting to keep {:.0} of {} samples with distance <= {}",
+        kept_fraction * oracle.samples.len() as f64,
+        oracle.samples.len(),
+        max_distance
+    );
+    let distances = with_samples!(&mut oracle.samples, samples => samples
+        .par_iter_mut()
+        .map(|query| query.with_wide(|query| code.decode_sample_with_distance(query)))
+        .collect::<Vec<u32>>());
+
+    let mut distances = distances.into_iter();
+    let mut bias_sum = 0.0;
+    let delta_s = oracle.delta_s;
+    with_samples!(&mut oracle.samples, samples => samples.retain(|_| {
+        let distance = distances.next().unwrap();
+        if distance <= max_distance {
+            bias_sum += delta_s.powi(distance as i32);
+            true
+        } else {
+            false
+        }
+    }));
+    assert!(
+        !oracle.samples.is_empty(),
+        "No samples left after filtering"
+    );
+    let bias = bias_sum / oracle.samples.len() as f64;
+
+    transform_secret(oracle, code);
+    oracle.delta *= bias;
+    log::debug!(
+        "Kept {} samples, new delta = {} (unfiltered bias: {})",
+        oracle.samples.len(),
+        oracle.delta,
+        code.bias(delta_s)
+    );
+}
+
+/// Reduce using the covering codes attack and weigh the samples by their decoding distance
+///
+/// Returns a weight for every sample for [`fwht_solve_weighted`](crate::lf1::fwht_solve_weighted):
+/// a sample at distance `$w$` from the nearest codeword has bias `$d * d_s^w$`.
+///
+/// $k' = dim(G)$
+/// $n' = n$
+/// $d' = d * bc$, on average over the samples
+/// $d'_s$ depends on $d_s$ and $G$.
+pub fn code_reduce_weighted<T: BinaryCode + Sync>(oracle: &mut LpnOracle, code: &T) -> Vec<f64> {
+    assert!(
+        oracle.delta_s > 0.0,
+        "This reduction only works for sparse secrets!"
+    );
+    assert_eq!(
+        oracle.get_k(),
+        code.length(),
+        "The length of the code does not match the problem size!"
+    );
+
+    log::info!("Decoding and weighing samples");
+    let (delta, delta_s) = (oracle.delta, oracle.delta_s);
+    let weights = with_samples!(&mut oracle.samples, samples => samples
+        .par_iter_mut()
+        .map(|query| {
+            let distance = query.with_wide(|query| code.decode_sample_with_distance(query));
+            log_likelihood_weight(delta * delta_s.powi(distance as i32))
+        })
+        .collect());
+
+    transform_secret(oracle, code);
+    apply_code_bias(oracle, code);
+    weights
+}
+
+/// Reduce the window of coordinates `start..start + code.length()` using the covering codes attack
+///
+/// See [`code_reduce_positions`].
+pub fn code_reduce_window<T: BinaryCode + Sync>(oracle: &mut LpnOracle, code: &T, start: usize) {
+    let positions = (start..(start + code.length())).collect::<Vec<_>>();
+    code_reduce_positions(oracle, code, &positions);
+}
+
+/// Reduce the coordinates at `positions` using the covering codes attack
+///
+/// Unlike [`code_reduce`], this leaves the other coordinates untouched, so they can still be
+/// reduced later. The decoded message is stored in the lowest `dim(G)` coordinates,
+/// followed by the untouched coordinates in their original order.
+/// The secret `s` becomes `(s_p G^T, s_r)`, with `s_p` the secret on the positions
+/// and `s_r` the rest.
+///
+/// $k' = k - len(G) + dim(G)$
+/// $n' = n$
+/// $d' = d * bc$
+pub fn code_reduce_positions<T: BinaryCode + Sync>(
+    oracle: &mut LpnOracle,
+    code: &T,
+    positions: &[usize],
+) {
+    assert!(
+        oracle.delta_s > 0.0,
+        "This reduction only works for sparse secrets!"
+    );
+    let k = oracle.get_k();
+    assert_eq!(
+        positions.len(),
+        code.length(),
+        "The length of the code does not match the number of positions!"
+    );
+    let mut in_positions = vec![false; k];
+    for &pos in positions {
+        assert!(pos < k, "Position {} is out of range", pos);
+        assert!(!in_positions[pos], "Position {} occurs twice", pos);
+        in_positions[pos] = true;
+    }
+    let rest = (0..k).filter(|&i| !in_positions[i]).collect::<Vec<_>>();
 
-    //log::trace!("Computing new delta");
-    //oracle.delta *= code.bias(oracle.delta_s);
-    //log::debug!("New delta = {}", oracle.delta);
+    log::info!(
+        "Decoding {} coordinates of the samples with a [{}, {}] code",
+        positions.len(),
+        code.length(),
+        code.dimension()
+    );
+    let reduce = |a: &BinVector, message: BinVector| {
+        let mut result = message;
+        for &i in &rest {
+            result.push(a.get(i).unwrap());
+        }
+        result
+    };
+    with_samples!(&mut oracle.samples, samples => samples.par_iter_mut().for_each(|query| {
+        let a = query.as_binvector(k);
+        let window = BinVector::from_function(positions.len(), |i| a.get(positions[i]).unwrap());
+        let message = code.decode_to_message(&window).unwrap();
+        query.set_from_binvec(&reduce(&a, message));
+    }));
+
+    log::warn!(
+        "Note that we transformed the secret on the positions $s_p$ into $s_p'=s_p*G^T$, k' = {}!",
+        k - code.length() + code.dimension()
+    );
+    let secret = oracle.secret.as_binvector(k);
+    let secret_window =
+        BinVector::from_function(positions.len(), |i| secret.get(positions[i]).unwrap());
+    let gen_t = code.generator_matrix().transposed();
+    oracle.secret = Sample::from_binvector(&reduce(&secret, &secret_window * &gen_t), false);
+
+    unsafe { oracle.set_k(k - code.length() + code.dimension()) };
+    apply_code_bias(oracle, code);
 }
 
 #[cfg(test)]
@@ -201,6 +412,21 @@ mod test {
         assert_eq!(secret, unsps, "sparse/unsparse unequal");
     }
 
+    #[test]
+    fn sparse_samples_consistent() {
+        // without noise, every transformed sample should match the new secret exactly
+        let mut oracle: LpnOracle = LpnOracle::new(100, 0.0);
+        oracle.get_samples(10_000);
+        sparse_secret_reduce(&mut oracle);
+        assert_eq!(oracle.samples.len(), 10_000 - 100);
+        let secret = oracle.secret.clone();
+        assert!(oracle
+            .samples
+            .to_vec()
+            .iter()
+            .all(|q| q.vector_product(&secret, 100) == q.get_product()));
+    }
+
     #[cfg(feature = "hamming")]
     #[test]
     fn test_reduction() {
@@ -225,4 +451,94 @@ mod test {
         let fwht_solution = fwht_solve(oracle.clone());
         assert_eq!(secret, fwht_solution, "Found wrong solution");
     }
+
+    #[cfg(feature = "hamming")]
+    #[test]
+    fn test_reduction_stream() {
+        use crate::codes::HammingCode15_11;
+        use crate::lf1::fwht_solve_streaming;
+
+        let mut oracle: LpnOracle = LpnOracle::new(15, 1.0 / 16.0);
+        oracle.secret = Sample::from_binvector(&BinVector::from_function(15, |x| x == 3), false);
+        oracle.delta_s = 0.875;
+        let stream = SampleStream::new(oracle, 200_000).with_chunk_size(10_000);
+        let stream = code_reduce_stream(stream, &HammingCode15_11);
+        let secret = stream.oracle().secret.as_binvector(11);
+        assert_eq!(stream.oracle().get_k(), 11);
+        assert_eq!(fwht_solve_streaming(stream), secret);
+    }
+
+    #[cfg(feature = "hamming")]
+    #[test]
+    fn test_reduction_positions() {
+        use crate::codes::HammingCode7_4;
+
+        // the secret is zero on the positions, so without noise the samples stay consistent
+        let positions = [1, 4, 5, 9, 12, 13, 18];
+        let mut oracle: LpnOracle = LpnOracle::new(20, 0.0);
+        oracle.secret = Sample::from_binvector(
+            &BinVector::from_function(20, |x| x % 3 == 0 && !positions.contains(&x)),
+            false,
+        );
+        oracle.delta_s = 0.5;
+        oracle.get_samples(1000);
+
+        code_reduce_positions(&mut oracle, &HammingCode7_4, &positions);
+        assert_eq!(oracle.get_k(), 17);
+        // an eighth of the windows are codewords, the others are at distance 1
+        assert!((oracle.delta - (0.125 + 0.875 * 0.5)).abs() < 1e-10);
+        let secret = oracle.secret.as_binvector(17);
+        assert_eq!(
+            secret,
+            BinVector::from_function(17, |x| x >= 4 && [0, 3, 6, 15].contains(&rest(x)))
+        );
+        for query in &oracle.samples.to_vec() {
+            assert_eq!(
+                query.vector_product(&oracle.secret, 17),
+                query.get_product()
+            );
+        }
+
+        fn rest(x: usize) -> usize {
+            [0, 2, 3, 6, 7, 8, 10, 11, 14, 15, 16, 17, 19][x - 4]
+        }
+    }
+
+    #[cfg(feature = "hamming")]
+    #[test]
+    fn test_reduction_filtered() {
+        use crate::codes::HammingCode15_11;
+        use crate::lf1::fwht_solve;
+
+        let mut oracle: LpnOracle = LpnOracle::new(15, 1.0 / 8.0);
+        oracle.get_samples(100_000);
+        sparse_secret_reduce(&mut oracle);
+        let delta = oracle.delta;
+
+        // Hamming codes are perfect, a sixteenth of the words are codewords
+        code_reduce_filtered(&mut oracle, &HammingCode15_11, 0);
+        assert!((5_500..7_000).contains(&oracle.samples.len()));
+        assert!((oracle.delta - delta).abs() < 1e-10);
+        let secret = oracle.secret.as_binvector(oracle.get_k());
+        assert_eq!(secret, fwht_solve(oracle), "Found wrong solution");
+    }
+
+    #[cfg(feature = "hamming")]
+    #[test]
+    fn test_reduction_weighted() {
+        use crate::codes::HammingCode15_11;
+        use crate::lf1::fwht_solve_weighted;
+
+        let mut oracle: LpnOracle = LpnOracle::new(15, 1.0 / 8.0);
+        oracle.get_samples(20_000);
+        sparse_secret_reduce(&mut oracle);
+
+        let delta = oracle.delta;
+        let weights = code_reduce_weighted(&mut oracle, &HammingCode15_11);
+        assert_eq!(weights.len(), oracle.samples.len());
+        let bias = HammingCode15_11.bias(0.75);
+        assert!((oracle.delta - delta * bias).abs() < 1e-10);
+        let secret = oracle.secret.as_binvector(oracle.get_k());
+        assert_eq!(secret, fwht_solve_weighted(oracle, &weights));
+    }
 }
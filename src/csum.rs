@@ -0,0 +1,236 @@
+//! The c-sum-BKW reduction by Esser, Heuer, Kübler, May and Sohler
+//!
+//! Instead of adding pairs of samples that collide on a window of `b` bits,
+//! we add `c`-tuples of samples that sum to zero on the window.
+//! This finds many more combinations than there are samples, so we can keep the number
+//! of samples constant while using far fewer than the `2^b` BKW would need.
+use crate::{
+    bkw::create_pivots,
+    oracle::{query_bits_range, with_samples, LpnOracle, Sample},
+    random::lpn_thread_rng,
+    util::log_2,
+};
+use rand::prelude::*;
+use rayon::prelude::*;
+use std::ops;
+
+/// c-sum reduction
+///
+/// For `c = 3` we use the naive search: for every sample we walk through the other samples
+/// and look up the last one in the table of samples sorted by their window.
+/// For `c = 4` we join pairs of samples, see [`four_sums`].
+/// We produce at most one new sample for every sample, so the number of samples
+/// does not grow.
+///
+/// `$k' = k - b$`
+/// `$n' \leq n$`
+/// `$\delta' = \delta^c$`
+pub fn c_sum_reduce(oracle: &mut LpnOracle, b: u32, c: u32) {
+    let k = oracle.get_k();
+    let b = b as usize;
+    let c = c as usize;
+    assert!(c == 3 || c == 4, "Only c = 3 and c = 4 are supported");
+    assert!(b < k, "b < k");
+    assert!(b < 64, "b should fit in a u64");
+    let n = oracle.samples.len();
+    assert!(n > c, "Need more samples than c");
+
+    log::info!(
+        "{}-sum reduction, b={}, {} samples (log2: {}), expect {} (log2: {}) {}-sums",
+        c,
+        b,
+        n,
+        log_2(n),
+        expected_solutions(n, b, c),
+        expected_solutions(n, b, c).log2() as u32,
+        c
+    );
+
+    let bitrange: ops::Range<usize> = (k - b)..k;
+    with_samples!(&mut oracle.samples, samples => {
+        log::debug!("Sorting samples and collecting pivots");
+        let pivots = create_pivots(samples, &bitrange);
+
+        log::debug!("Searching {}-sums", c);
+        *samples = if c == 3 {
+            three_sums(samples, &pivots, &bitrange)
+        } else {
+            four_sums(samples, &pivots, &bitrange)
+        };
+    });
+    oracle.truncate(k - b);
+    oracle.delta = oracle.delta.powi(c as i32);
+    log::debug!(
+        "{}-sum reduction done, {} samples (2^{}) now, k' = {}",
+        c,
+        oracle.samples.len(),
+        log_2(oracle.samples.len()),
+        oracle.get_k()
+    );
+}
+
+/// Find a 3-sum for every sample by walking through its partners
+///
+/// The samples should be sorted on `bitrange`, with `pivots` the ends of the partitions.
+fn three_sums<const N: usize>(
+    samples: &[Sample<N>],
+    pivots: &[usize],
+    bitrange: &ops::Range<usize>,
+) -> Vec<Sample<N>> {
+    let n = samples.len();
+    let partition = |window: u64| {
+        let window = window as usize;
+        let left = if window == 0 { 0 } else { pivots[window - 1] };
+        left..pivots[window]
+    };
+
+    (0..n)
+        .into_par_iter()
+        .map_init(lpn_thread_rng, |rng, anchor| {
+            // walk through the partners from a random offset to spread the use of samples
+            let offset = rng.gen_range(1..n);
+            (0..(n - 1)).find_map(|t| {
+                let partner = (anchor + offset + t) % n;
+                let mut sum = samples[anchor].clone();
+                sum.xor_into(&samples[partner]);
+                let range = partition(query_bits_range(&sum, bitrange.clone()));
+                if range.is_empty() {
+                    return None;
+                }
+                let last = rng.gen_range(range);
+                if last == anchor || last == partner {
+                    return None;
+                }
+                sum.xor_into(&samples[last]);
+                debug_assert_eq!(query_bits_range(&sum, bitrange.clone()), 0);
+                Some(sum)
+            })
+        })
+        .flatten()
+        .collect()
+}
+
+/// Find up to `n` 4-sums by joining pairs of samples
+///
+/// Split the window in the top `l` bits and the other `b - l` bits, with `2^l` about `n / 2`.
+/// For a random target `t` on the top bits, we list the about `n` pairs that sum to `t` there.
+/// Two such pairs sum to zero on the top bits, so we sort the pairs on the other bits and add
+/// the pairs that collide. Every target costs `O(n log n)` and we try at most `2^l` targets.
+///
+/// The samples should be sorted on `bitrange`, with `pivots` the ends of the partitions.
+fn four_sums<const N: usize>(
+    samples: &[Sample<N>],
+    pivots: &[usize],
+    bitrange: &ops::Range<usize>,
+) -> Vec<Sample<N>> {
+    let n = samples.len();
+    let b = bitrange.len();
+    let l = std::cmp::min(b, std::cmp::max(1, log_2(n) as usize - 1));
+    let low_bits = b - l;
+    let low_mask = (1u64 << low_bits) - 1;
+    let windows = samples
+        .par_iter()
+        .map(|q| query_bits_range(q, bitrange.clone()))
+        .collect::<Vec<u64>>();
+    // the samples are sorted on the window, so the top bits give contiguous groups
+    let group = |high: u64| {
+        let first = (high as usize) << low_bits;
+        let last = ((high as usize + 1) << low_bits) - 1;
+        let left = if first == 0 { 0 } else { pivots[first - 1] };
+        left..pivots[last]
+    };
+    log::trace!("Joining pairs on {} of {} bits", low_bits, b);
+
+    let mut targets = (0..(1u64 << l)).collect::<Vec<_>>();
+    targets.shuffle(&mut lpn_thread_rng());
+    let mut new_samples = Vec::with_capacity(n);
+    for target in targets {
+        if new_samples.len() >= n {
+            break;
+        }
+        // all pairs (i, j) with i < j that sum to target on the top bits, by their other bits
+        let windows = &windows;
+        let mut pairs = (0..(1u64 << l))
+            .into_par_iter()
+            .filter(|&high| high <= high ^ target)
+            .flat_map_iter(|high| {
+                let left = group(high);
+                let right = group(high ^ target);
+                left.flat_map(move |i| {
+                    let right = if target == 0 {
+                        (i + 1)..right.end
+                    } else {
+                        right.clone()
+                    };
+                    right.map(move |j| ((windows[i] ^ windows[j]) & low_mask, i, j))
+                })
+            })
+            .collect::<Vec<(u64, usize, usize)>>();
+        pairs.par_sort_unstable_by_key(|&(low, _, _)| low);
+
+        let runs = pairs.chunk_by(|a, b| a.0 == b.0).collect::<Vec<_>>();
+        new_samples.par_extend(runs.into_par_iter().flat_map_iter(|run| {
+            run.chunks_exact(2).filter_map(|pairs| {
+                let (_, i, j) = pairs[0];
+                let (_, x, y) = pairs[1];
+                if i == x || i == y || j == x || j == y {
+                    return None;
+                }
+                let mut sum = samples[i].clone();
+                for &idx in &[j, x, y] {
+                    sum.xor_into(&samples[idx]);
+                }
+                debug_assert_eq!(query_bits_range(&sum, bitrange.clone()), 0);
+                Some(sum)
+            })
+        }));
+    }
+    new_samples.truncate(n);
+    new_samples
+}
+
+/// The expected number of `c`-tuples out of `n` samples that sum to zero on `b` bits
+fn expected_solutions(n: usize, b: usize, c: usize) -> f64 {
+    (0..c)
+        .map(|i| (n - i) as f64 / (i + 1) as f64)
+        .product::<f64>()
+        / 2f64.powi(b as i32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lf1::fwht_solve;
+
+    #[test]
+    fn run_3_sum() {
+        let mut oracle = LpnOracle::new(24, 1.0 / 16.0);
+        oracle.get_samples(4000);
+        let secret = oracle.secret.clone();
+        c_sum_reduce(&mut oracle, 8, 3);
+        assert_eq!(oracle.get_k(), 16);
+        assert!(oracle.samples.len() > 3900);
+        assert_eq!(fwht_solve(oracle), secret.as_binvector(16));
+    }
+
+    #[test]
+    fn run_4_sum() {
+        let mut oracle = LpnOracle::new(24, 1.0 / 16.0);
+        oracle.get_samples(4000);
+        let secret = oracle.secret.clone();
+        c_sum_reduce(&mut oracle, 12, 4);
+        assert_eq!(oracle.get_k(), 12);
+        assert_eq!(fwht_solve(oracle), secret.as_binvector(12));
+    }
+
+    #[test]
+    fn run_4_sum_large() {
+        let mut oracle = LpnOracle::new(40, 1.0 / 32.0);
+        oracle.get_samples(200_000);
+        let secret = oracle.secret.clone();
+        c_sum_reduce(&mut oracle, 20, 4);
+        assert_eq!(oracle.get_k(), 20);
+        assert_eq!(oracle.samples.len(), 200_000);
+        assert_eq!(fwht_solve(oracle), secret.as_binvector(20));
+    }
+}
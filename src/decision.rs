@@ -0,0 +1,164 @@
+//! Distinguishers for the decision version of LPN
+//!
+//! Instead of recovering the secret, these tell LPN samples apart from uniformly random samples.
+//! The distinguishers first reduce the problem using the existing reductions and then
+//! test whether the Walsh spectrum or the majority counts deviate from what uniform samples
+//! would give.
+//!
+//! Use [`LpnOracle::new_uniform`] to calibrate against uniform samples.
+use crate::{
+    bkw::bkw_reduce,
+    lf1::{count_samples, parfwht, xor_reduce},
+    oracle::{with_samples, LpnOracle},
+};
+
+#[cfg(feature = "codes")]
+use crate::{codes::BinaryCode, covering_codes::code_reduce};
+
+/// The outcome of a decision-LPN distinguisher
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decision {
+    /// The observed test statistic, normalised such that it is roughly
+    /// standard normal for a single candidate on uniform samples
+    pub statistic: f64,
+    /// The threshold above which we decide the samples were LPN samples
+    pub threshold: f64,
+    /// The empirical bias of the best candidate
+    pub empirical_bias: f64,
+    /// Estimate of the distinguishing advantage given the bias `oracle.delta`
+    pub advantage: f64,
+    /// Do we think these were LPN samples?
+    pub is_lpn: bool,
+}
+
+/// Decide based on the best correlation `best` out of `candidates` candidates over `n` samples.
+///
+/// For uniform samples, every correlation is a sum of `n` independent ±1 values,
+/// so by Hoeffding and a union bound the largest normalised correlation exceeds `t` with
+/// probability at most `candidates * exp(-t^2 / 2)`.
+/// For LPN samples, the correct candidate has correlation around `n * delta`.
+fn decide(best: i64, n: usize, candidates: f64, delta: f64) -> Decision {
+    let sqrt_n = (n as f64).sqrt();
+    let statistic = best as f64 / sqrt_n;
+    let null_max = (2.0 * candidates.ln()).sqrt();
+    let alt_mean = delta * sqrt_n;
+    let threshold = if alt_mean > null_max {
+        (null_max + alt_mean) / 2.0
+    } else {
+        null_max
+    };
+
+    let false_positive = (candidates * (-threshold.powi(2) / 2.0).exp()).min(1.0);
+    let false_negative = if alt_mean > threshold {
+        (-(alt_mean - threshold).powi(2) / 2.0).exp()
+    } else {
+        1.0
+    };
+    let advantage = (1.0 - false_positive - false_negative).max(0.0);
+
+    log::debug!(
+        "Distinguisher statistic {:.3}, threshold {:.3}, estimated advantage {:.5}",
+        statistic,
+        threshold,
+        advantage
+    );
+
+    Decision {
+        statistic,
+        threshold,
+        empirical_bias: best as f64 / n as f64,
+        advantage,
+        is_lpn: statistic > threshold,
+    }
+}
+
+/// Distinguish using the Fast Walsh-Hadamard Transform
+///
+/// Computes the correlation of every candidate secret with the samples
+/// and tests whether the largest one is larger than expected for uniform samples.
+pub fn fwht_distinguish(oracle: LpnOracle) -> Decision {
+    let k = oracle.get_k() as u32;
+    assert!((k as usize) < crate::util::num_bits::<usize>());
+    let n = oracle.samples.len();
+    assert!(n > 0, "What, no samples?");
+    let delta = oracle.delta;
+    log::info!("FWHT distinguisher for k' = {}, n = {}", k, n);
+
+    let mut majority_counter = count_samples(oracle);
+    parfwht(&mut majority_counter[..], k);
+    let best = majority_counter.into_iter().max().unwrap();
+
+    decide(best, n, 2f64.powi(k as i32), delta)
+}
+
+/// Distinguish using the majority of the samples that have been reduced to `a = 0`
+///
+/// The products of these samples are just the noise bits, which are biased for LPN samples.
+pub fn majority_distinguish(oracle: LpnOracle) -> Decision {
+    let delta = oracle.delta;
+    let (n, sum) = with_samples!(&oracle.samples, samples => samples
+    .iter()
+    .filter(|q| q.count_ones() == 0)
+    .fold((0usize, 0i64), |(n, sum), q| {
+        (n + 1, sum + if q.get_product() { -1 } else { 1 })
+    }));
+    assert!(n > 0, "No samples with a = 0 left to decide on");
+    log::info!("Majority distinguisher on {} samples with a = 0", n);
+
+    decide(sum, n, 1.0, delta)
+}
+
+/// Reduce with `a-1` applications of [`partition_reduce`](crate::bkw::partition_reduce)
+/// and distinguish via the FWHT.
+pub fn partition_distinguish(mut oracle: LpnOracle, a: u32, b: u32) -> Decision {
+    bkw_reduce(&mut oracle, a, b);
+    oracle.delta = oracle.delta.powi(2i32.pow(a - 1));
+    fwht_distinguish(oracle)
+}
+
+/// Reduce with `a-1` applications of [`xor_reduce`] and distinguish via the FWHT.
+pub fn xor_distinguish(mut oracle: LpnOracle, a: u32, b: u32) -> Decision {
+    for _ in 1..a {
+        xor_reduce(&mut oracle, b);
+    }
+    fwht_distinguish(oracle)
+}
+
+/// Reduce with the covering-codes reduction and distinguish via the FWHT.
+///
+/// Requires a sparse secret, see [`sparse_secret_reduce`](crate::covering_codes::sparse_secret_reduce).
+#[cfg(feature = "codes")]
+pub fn code_distinguish<T: BinaryCode + Sync>(mut oracle: LpnOracle, code: &T) -> Decision {
+    code_reduce(&mut oracle, code);
+    fwht_distinguish(oracle)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn distinguish_fwht() {
+        let mut oracle = LpnOracle::new(10, 1.0 / 8.0);
+        oracle.get_samples(10_000);
+        let decision = fwht_distinguish(oracle);
+        assert!(decision.is_lpn, "{:?}", decision);
+        assert!(decision.advantage > 0.99, "{:?}", decision);
+
+        let mut oracle = LpnOracle::new_uniform(10, 1.0 / 8.0);
+        oracle.get_samples(10_000);
+        let decision = fwht_distinguish(oracle);
+        assert!(!decision.is_lpn, "{:?}", decision);
+    }
+
+    #[test]
+    fn distinguish_partition() {
+        let mut oracle = LpnOracle::new(24, 1.0 / 16.0);
+        oracle.get_samples(200_000);
+        assert!(partition_distinguish(oracle, 3, 8).is_lpn);
+
+        let mut oracle = LpnOracle::new_uniform(24, 1.0 / 16.0);
+        oracle.get_samples(200_000);
+        assert!(!partition_distinguish(oracle, 3, 8).is_lpn);
+    }
+}
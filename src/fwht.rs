@@ -0,0 +1,381 @@
+//! Memory-lean variants of the FWHT solver for larger final dimensions
+//!
+//! [`fwht_solve`](crate::lf1::fwht_solve) keeps two tables of `2^k` 64-bit counters.
+//! The solvers here use counters that are only as wide as the number of samples requires,
+//! and can work on blocks of the table at a time: streaming through a file on disk,
+//! or only evaluating a subset of the candidates.
+//!
+//! All of them sort the samples by their `a`, so we can count per block of the table.
+use crate::{
+    lf1::{argmax, candidate_to_binvector, solve_counters},
+    oracle::{LpnOracle, Sample, Samples},
+};
+use m4ri_rust::friendly::BinVector;
+use rayon::prelude::*;
+use std::{
+    fmt::Debug,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    mem, ops,
+    path::{Path, PathBuf},
+};
+
+/// A signed integer type used to count samples in the FWHT
+///
+/// The absolute value of any entry of the transform is at most the number of samples,
+/// so we pick the narrowest type that can hold it.
+pub trait Counter:
+    Copy
+    + Default
+    + Debug
+    + Send
+    + Sync
+    + Ord
+    + Into<i64>
+    + ops::Add<Output = Self>
+    + ops::Sub<Output = Self>
+    + ops::AddAssign
+    + ops::SubAssign
+{
+    /// The largest value this counter can hold
+    const MAX: i64;
+    /// The value one
+    const ONE: Self;
+}
+
+impl Counter for i16 {
+    const MAX: i64 = i16::MAX as i64;
+    const ONE: Self = 1;
+}
+
+impl Counter for i32 {
+    const MAX: i64 = i32::MAX as i64;
+    const ONE: Self = 1;
+}
+
+impl Counter for i64 {
+    const MAX: i64 = i64::MAX;
+    const ONE: Self = 1;
+}
+
+/// Run `$solver` with the narrowest counter that can count `$n` samples
+macro_rules! with_counter {
+    ($n:expr, $solver:ident($($arg:expr),*)) => {{
+        let n = $n as i64;
+        if n <= <i16 as Counter>::MAX {
+            log::debug!("Using 16-bit counters");
+            $solver::<i16>($($arg),*)
+        } else if n <= <i32 as Counter>::MAX {
+            log::debug!("Using 32-bit counters");
+            $solver::<i32>($($arg),*)
+        } else {
+            log::debug!("Using 64-bit counters");
+            $solver::<i64>($($arg),*)
+        }
+    }};
+}
+
+/// Solving using the FWHT with counters as narrow as possible
+///
+/// Uses `2^k` counters of 16, 32 or 64 bits, depending on the number of samples.
+pub fn fwht_solve_lean(mut oracle: LpnOracle) -> BinVector {
+    let (k, samples) = prepare(&mut oracle);
+    log::info!("Lean FWHT solving for k' = {}", k);
+    with_counter!(samples.len(), lean_solve(samples, k))
+}
+
+fn lean_solve<T: Counter>(samples: &[Sample<1>], k: u32) -> BinVector {
+    let mut table = vec![T::default(); 1 << k];
+    // count in parallel on disjoint parts of the table
+    let chunk_size = std::cmp::max((1 << k) / rayon::current_num_threads(), 1);
+    table
+        .par_chunks_mut(chunk_size)
+        .enumerate()
+        .for_each(|(chunk, table)| {
+            let offset = (chunk * chunk_size) as u64;
+            count_block(samples, offset, table);
+        });
+    solve_counters(&mut table, k, |table, k| parfwht_rows(table, k, 1), argmax)
+}
+
+/// Solving using the FWHT on a table stored on disk
+///
+/// We only keep blocks of `2^block_bits` counters in memory.
+/// First we transform every block on the lowest `block_bits` bits and write it to a temporary
+/// file in `dir`, then we read the blocks back column by column to transform on the highest bits.
+/// Requires `block_bits >= k - block_bits`.
+pub fn fwht_solve_out_of_core(
+    mut oracle: LpnOracle,
+    block_bits: u32,
+    dir: &Path,
+) -> io::Result<BinVector> {
+    let (k, samples) = prepare(&mut oracle);
+    assert!(block_bits <= k, "Blocks can't be larger than the table");
+    assert!(
+        2 * block_bits >= k,
+        "Need block_bits >= k - block_bits to transform the columns in memory"
+    );
+    log::info!(
+        "Out-of-core FWHT solving for k' = {} with blocks of 2^{} counters",
+        k,
+        block_bits
+    );
+    let guess = with_counter!(
+        samples.len(),
+        out_of_core_solve(samples, k, block_bits, dir)
+    )?;
+    Ok(candidate_to_binvector(guess, k))
+}
+
+/// A file that is removed when dropped
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.0) {
+            log::warn!("Could not remove {:?}: {}", self.0, e);
+        }
+    }
+}
+
+fn out_of_core_solve<T: Counter>(
+    samples: &[Sample<1>],
+    k: u32,
+    block_bits: u32,
+    dir: &Path,
+) -> io::Result<u64> {
+    let high_bits = k - block_bits;
+    let block_len = 1usize << block_bits;
+    let counter_size = mem::size_of::<T>() as u64;
+
+    let path = TempFile(dir.join(format!(
+        "lpn-fwht-{}-{:016x}.bin",
+        std::process::id(),
+        rand::random::<u64>()
+    )));
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path.0)?;
+    file.set_len(counter_size << k)?;
+
+    log::debug!("Transforming {} blocks on the low bits", 1 << high_bits);
+    let mut table = vec![T::default(); block_len];
+    for block in 0..(1u64 << high_bits) {
+        table.iter_mut().for_each(|c| *c = T::default());
+        count_block(samples, block << block_bits, &mut table);
+        parfwht_rows(&mut table, block_bits, 1);
+        file.write_all(as_bytes(&table))?;
+    }
+    drop(table);
+
+    log::debug!("Transforming columns on the high bits");
+    let column_len = 1usize << (block_bits - high_bits);
+    let mut columns = vec![T::default(); column_len << high_bits];
+    let mut best: Option<(T, u64)> = None;
+    for column in 0..(block_len / column_len) {
+        for (block, row) in columns.chunks_mut(column_len).enumerate() {
+            let position = (block * block_len + column * column_len) as u64;
+            read_at(&mut file, position * counter_size, row)?;
+        }
+        parfwht_rows(&mut columns, high_bits, column_len);
+        let (idx, value) = columns
+            .par_iter()
+            .enumerate()
+            .max_by_key(|(_, value)| **value)
+            .unwrap();
+        if best.is_none_or(|(best_value, _)| *value > best_value) {
+            let (high, low) = (idx / column_len, column * column_len + idx % column_len);
+            best = Some((*value, ((high << block_bits) | low) as u64));
+        }
+    }
+    let (value, guess) = best.unwrap();
+    log::debug!("Best candidate has correlation {:?}", value);
+    Ok(guess)
+}
+
+/// Solving using a pruned FWHT that only evaluates some of the candidates
+///
+/// Only considers the candidates whose highest `k - block_bits` bits are in `high_candidates`,
+/// for example those of low weight for a sparse secret.
+/// Keeps `(high_candidates.len() + 1) * 2^block_bits` counters in memory.
+pub fn fwht_solve_pruned(
+    mut oracle: LpnOracle,
+    block_bits: u32,
+    high_candidates: &[u64],
+) -> BinVector {
+    let (k, samples) = prepare(&mut oracle);
+    assert!(block_bits <= k, "Blocks can't be larger than the table");
+    assert!(!high_candidates.is_empty(), "Need at least one candidate");
+    assert!(
+        high_candidates.iter().all(|&c| c >> (k - block_bits) == 0),
+        "Candidates should have k - block_bits bits"
+    );
+    log::info!(
+        "Pruned FWHT solving for k' = {}, evaluating {} of {} high parts",
+        k,
+        high_candidates.len(),
+        1u64 << (k - block_bits)
+    );
+    let guess = with_counter!(
+        samples.len(),
+        pruned_solve(samples, k, block_bits, high_candidates)
+    );
+    candidate_to_binvector(guess, k)
+}
+
+fn pruned_solve<T: Counter>(
+    samples: &[Sample<1>],
+    k: u32,
+    block_bits: u32,
+    high_candidates: &[u64],
+) -> u64 {
+    let block_len = 1usize << block_bits;
+    let mut table = vec![T::default(); block_len];
+    let mut results = vec![vec![T::default(); block_len]; high_candidates.len()];
+    for block in 0..(1u64 << (k - block_bits)) {
+        table.iter_mut().for_each(|c| *c = T::default());
+        count_block(samples, block << block_bits, &mut table);
+        parfwht_rows(&mut table, block_bits, 1);
+        // the high bits of the transform are a signed sum over the blocks
+        results
+            .par_iter_mut()
+            .zip(high_candidates.par_iter())
+            .for_each(|(result, &candidate)| {
+                if (block & candidate).count_ones() % 2 == 1 {
+                    result.iter_mut().zip(&table).for_each(|(r, t)| *r -= *t);
+                } else {
+                    result.iter_mut().zip(&table).for_each(|(r, t)| *r += *t);
+                }
+            });
+    }
+
+    let (candidate, low, value) = results
+        .par_iter()
+        .zip(high_candidates.par_iter())
+        .map(|(result, &candidate)| {
+            let (low, value) = result
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, value)| **value)
+                .unwrap();
+            (candidate, low as u64, *value)
+        })
+        .max_by_key(|(_, _, value)| *value)
+        .unwrap();
+    log::debug!("Best candidate has correlation {:?}", value);
+    (candidate << block_bits) | low
+}
+
+/// Fast Walsh Hamadard Transform on rows of `row_len` elements
+///
+/// Transforms `data` as a vector of `2^bits` rows, adding and subtracting whole rows.
+/// With `row_len = 1` this is the usual transform, see [`parfwht`](crate::lf1::parfwht).
+pub fn parfwht_rows<T: Counter>(data: &mut [T], bits: u32, row_len: usize) {
+    assert_eq!(data.len(), row_len << bits);
+
+    let mut stride = (1usize << bits) / 2;
+    while stride >= 1 {
+        data.par_chunks_mut(2 * stride * row_len).for_each(|data| {
+            let (left, right) = data.split_at_mut(stride * row_len);
+            left.iter_mut().zip(right.iter_mut()).for_each(|(l, r)| {
+                let (a, b) = (*l, *r);
+                *l = a + b;
+                *r = a - b;
+            });
+        });
+        stride >>= 1;
+    }
+}
+
+/// Sort the samples by `a`, so every block of the table corresponds to a range of samples
+///
+/// Returns `k` and the samples, which are stored in a single block for `k < 64`.
+fn prepare(oracle: &mut LpnOracle) -> (u32, &[Sample<1>]) {
+    let k = oracle.get_k();
+    assert!(k < 64, "Candidates should fit in a u64");
+    assert!(!oracle.samples.is_empty(), "What, no samples?");
+    let samples = match &mut oracle.samples {
+        Samples::Blocks1(samples) => samples,
+        _ => unreachable!("The samples should be repacked into a single block for k < 64"),
+    };
+    log::trace!("Sorting samples");
+    samples.par_sort_unstable_by_key(|q| q.get_block(0));
+    (k as u32, samples)
+}
+
+/// Count the sorted samples with `offset <= a < offset + table.len()`
+fn count_block<T: Counter>(samples: &[Sample<1>], offset: u64, table: &mut [T]) {
+    let end = offset + table.len() as u64;
+    let start_idx = samples.partition_point(|q| q.get_block(0) < offset);
+    let end_idx = samples.partition_point(|q| q.get_block(0) < end);
+    for sample in &samples[start_idx..end_idx] {
+        let idx = (sample.get_block(0) - offset) as usize;
+        if sample.get_product() {
+            table[idx] -= T::ONE;
+        } else {
+            table[idx] += T::ONE;
+        }
+    }
+}
+
+fn as_bytes<T: Counter>(data: &[T]) -> &[u8] {
+    // this is okay because the counters are plain integers
+    unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, mem::size_of_val(data)) }
+}
+
+fn read_at<T: Counter>(file: &mut File, position: u64, data: &mut [T]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(position))?;
+    // this is okay because any bit pattern is a valid counter
+    let bytes = unsafe {
+        std::slice::from_raw_parts_mut(data.as_mut_ptr() as *mut u8, mem::size_of_val(data))
+    };
+    file.read_exact(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lean_and_out_of_core() {
+        let mut oracle = LpnOracle::new(18, 1.0 / 8.0);
+        oracle.get_samples(20_000);
+        let secret = oracle.secret.as_binvector(18);
+        assert_eq!(fwht_solve_lean(oracle.clone()), secret);
+        let dir = std::env::temp_dir();
+        assert_eq!(fwht_solve_out_of_core(oracle, 10, &dir).unwrap(), secret);
+    }
+
+    #[test]
+    fn pruned() {
+        let mut oracle = LpnOracle::new(18, 1.0 / 8.0);
+        oracle.get_samples(20_000);
+        let secret = oracle.secret.as_binvector(18);
+        let high = (12..18).fold(0u64, |high, i| {
+            high | (secret.get(i).unwrap() as u64) << (i - 12)
+        });
+        let candidates = [high, high ^ 1, high ^ 0b100, 0];
+        assert_eq!(fwht_solve_pruned(oracle, 12, &candidates), secret);
+    }
+
+    #[test]
+    fn transform_rows() {
+        let data = (0..1024).map(|i| (i % 7) as i64 - 3).collect::<Vec<_>>();
+        let mut expected = data.clone();
+        crate::lf1::parfwht(&mut expected, 10);
+        let mut narrow = data.iter().map(|&x| x as i32).collect::<Vec<_>>();
+        parfwht_rows(&mut narrow, 10, 1);
+        assert_eq!(
+            narrow.into_iter().map(i64::from).collect::<Vec<_>>(),
+            expected
+        );
+
+        // the transform on 2^4 rows of 2^6 after transforming every row is the full transform
+        let mut rows = data;
+        rows.chunks_mut(64).for_each(|row| parfwht_rows(row, 6, 1));
+        parfwht_rows(&mut rows, 4, 64);
+        assert_eq!(rows, expected);
+    }
+}
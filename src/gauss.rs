@@ -1,6 +1,6 @@
 //! Defines the Pooled Gauss solving algorithms by Esser, Kübler and May
 use crate::{
-    oracle::{LpnOracle, StorageBlock},
+    oracle::{inner_products, with_samples, LpnOracle, Sample, StorageBlock},
     random::{lpn_thread_rng, ThreadRng},
 };
 use m4ri_rust::friendly::solve_left;
@@ -15,19 +15,19 @@ use std::{
 };
 
 /// Solves an LPN problem using Pooled Gauss
-#[allow(clippy::many_single_char_names, clippy::needless_pass_by_value)]
+#[allow(clippy::needless_pass_by_value)]
 pub fn pooled_gauss_solve(oracle: LpnOracle) -> BinVector {
+    with_samples!(&oracle.samples, samples => pooled_gauss(&oracle, samples))
+}
+
+/// [`pooled_gauss_solve`] on the samples in their current layout
+#[allow(clippy::many_single_char_names)]
+fn pooled_gauss<const N: usize>(oracle: &LpnOracle, samples: &[Sample<N>]) -> BinVector {
     let mut rng = lpn_thread_rng();
 
     let k = oracle.get_k();
-    let alpha = 0.5f64.powi(k as i32);
     let tau = (1.0 - oracle.delta) / 2.0;
-    let beta = ((1f64 - tau) / 2f64).powi(k as i32);
-    let m: f64 = (((1.5 * (1.0 / alpha).ln()).sqrt() + (1.0 / beta).ln().sqrt()) / (0.5 - tau))
-        .powi(2)
-        .floor();
-    let c = (tau * m + (3.0 * (0.5 - tau) * (1.0 / alpha).ln() * m).sqrt().floor()) as u32;
-    let m = m as usize;
+    let (m, c) = test_parameters(k, tau);
 
     log::info!(
         "Attempting Pooled Gauss solving method, k={}, tau={}",
@@ -35,55 +35,57 @@ pub fn pooled_gauss_solve(oracle: LpnOracle) -> BinVector {
         tau
     );
     log::trace!("Target secret weight <= {}", c);
-    log::trace!("Building (Am, b) with length {}", m);
-    let (am, bm) = sample_matrix(m, &oracle, &mut rng);
-    debug_assert_eq!(am.ncols(), k);
-    debug_assert_eq!(am.nrows(), m);
-    debug_assert_eq!(bm.nrows(), m);
-    debug_assert_eq!(bm.ncols(), 1);
-
-    let secret = &oracle.secret.as_binvector(k);
-
-    let test = |s_prime: &BinMatrix| {
-        debug_assert_eq!(s_prime.nrows(), k);
-        debug_assert_eq!(s_prime.ncols(), 1);
-
-        let mut testproduct = &am * s_prime;
-        testproduct += &bm;
-        let result = testproduct.count_ones() <= c;
-        debug_assert_eq!(
-            result,
-            &s_prime.as_vector() == secret,
-            "Test will reject or accept an (in)correct secret with weight {} <= {}",
-            testproduct.count_ones(),
-            c
-        );
-        result
+    log::trace!("Choosing {} test samples", m);
+    let test_samples = samples
+        .choose_multiple(&mut rng, m)
+        .cloned()
+        .collect::<Vec<Sample<N>>>();
+    debug_assert_eq!(test_samples.len(), m);
+
+    let secret = oracle
+        .is_secret_known()
+        .then(|| oracle.secret.as_binvector(k));
+
+    // test a batch of candidates, returns the index of the first accepted candidate
+    //
+    // The candidates have the product bit set, so the inner products are `A_m s' + b_m`.
+    let test = |candidates: &[Sample<N>]| {
+        let mut errors = vec![false; m];
+        (0..candidates.len()).find(|&idx| {
+            inner_products(&test_samples, &candidates[idx], &mut errors);
+            let weight = errors.iter().filter(|&&error| error).count() as u32;
+            let result = weight <= c;
+            if let Some(secret) = &secret {
+                debug_assert_eq!(
+                    result,
+                    &candidates[idx].as_binvector(k) == secret,
+                    "Test will reject or accept an (in)correct secret with weight {} <= {}",
+                    weight,
+                    c
+                );
+            }
+            result
+        })
     };
 
-    log::debug!("Starting random sampling of invertible (A, b)");
-
-    let s_prime_finder = move |(sender, rng): &mut (Arc<Mutex<Option<BinMatrix>>>, _), _| {
-        for _ in 0..10000 {
-            // find k-rank matrix
-            let (a, mut b) = loop {
-                let (a_try, b_try) = sample_matrix(k as usize, &oracle, rng);
-                // TODO is this check necessary?
-                // TODO avoid allocate?
-                if a_try.clone().echelonize() == k as usize {
-                    break (a_try, b_try);
+    log::debug!("Starting incremental sampling of invertible (A, b)");
+
+    let s_prime_finder = move |(sender, rng): &mut (Arc<Mutex<Option<BinVector>>>, _), _| {
+        let mut basis = IncrementalBasis::new(oracle, rng);
+        let mut candidates = Vec::with_capacity(TEST_BATCH_SIZE);
+        for _ in 0..(10000 / TEST_BATCH_SIZE) {
+            candidates.clear();
+            while candidates.len() < TEST_BATCH_SIZE {
+                let sample = samples.choose(rng).unwrap();
+                if basis.swap_in(sample, rng) {
+                    candidates.push(basis.extended_secret());
                 }
-            };
-            // A*s = b
-            if !solve_left(a, &mut b) {
-                log::warn!("Somehow, solving failed....");
-                continue;
             }
-            let result = { test(&b) };
-            if result {
-                println!("Found {:?}!", b.as_vector());
+            if let Some(idx) = test(&candidates) {
+                let s_prime = candidates[idx].as_binvector(k);
+                println!("Found {:?}!", s_prime);
                 let mut sender = sender.lock().unwrap();
-                sender.replace(b);
+                sender.replace(s_prime);
                 break;
             }
         }
@@ -102,26 +104,135 @@ pub fn pooled_gauss_solve(oracle: LpnOracle) -> BinVector {
         .try_for_each_init(|| (sender.clone(), lpn_thread_rng()), s_prime_finder);
 
     let sender = sender_parent.lock().unwrap();
-    let s_prime = sender.as_ref().unwrap();
+    sender.as_ref().unwrap().clone()
+}
+
+/// How many candidates we test at once
+const TEST_BATCH_SIZE: usize = 64;
+
+/// An invertible system ``A s = b`` of ``k`` samples that we update one sample at a time.
+///
+/// Instead of solving a fresh system for every candidate, we keep ``A^{-1}`` and swap
+/// a single sample in and out of ``A``, like the Canteaut-Chabaud variant of ISD.
+/// Each swap costs ``O(k^2)`` bit operations instead of ``O(k^3)``.
+struct IncrementalBasis<const N: usize> {
+    k: usize,
+    /// The rows of ``A^{-1}``
+    inverse: Vec<Sample<N>>,
+    /// The current solution ``s = A^{-1} b``
+    secret: Sample<N>,
+}
+
+impl<const N: usize> IncrementalBasis<N> {
+    /// Sample ``k`` queries until they are linearly independent and solve the system.
+    fn new(oracle: &LpnOracle, rng: &mut ThreadRng) -> IncrementalBasis<N> {
+        let k = oracle.get_k();
+        let (a, mut b) = loop {
+            let (a_try, b_try) = sample_matrix(k, oracle, rng);
+            if a_try.clone().echelonize() == k {
+                break (a_try, b_try);
+            }
+        };
+        let inverse = a.inverted();
+        let solved = solve_left(a, &mut b);
+        debug_assert!(solved, "Solving an invertible system failed");
+        IncrementalBasis {
+            k,
+            inverse: (0..k)
+                .map(|row| {
+                    Sample::from_binvector(
+                        &inverse.get_window(row, 0, row + 1, k).as_vector(),
+                        false,
+                    )
+                })
+                .collect(),
+            secret: Sample::from_binvector(&b.as_vector(), false),
+        }
+    }
 
-    s_prime.as_vector()
+    /// Replace one of the samples in ``A`` by ``sample``.
+    ///
+    /// Returns false if ``sample`` can't be swapped in, which only happens if it is zero.
+    fn swap_in(&mut self, sample: &Sample<N>, rng: &mut ThreadRng) -> bool {
+        let k = self.k;
+        // Express the new sample in terms of the current ones: a' = x A
+        let mut x = Sample::new();
+        for (row, inverse_row) in self.inverse.iter().enumerate() {
+            if sample.get_bit(row) {
+                x.xor_into(inverse_row);
+            }
+        }
+        // We can replace any sample i with x_i = 1.
+        let candidates = (0..k).filter(|&i| x.get_bit(i)).collect::<Vec<_>>();
+        let i = match candidates.choose(rng) {
+            Some(&i) => i,
+            None => return false,
+        };
+
+        // Sherman-Morrison: A'^{-1} = A^{-1} + w (x + e_i), with w the i-th column of A^{-1}.
+        // This leaves the i-th column w unchanged.
+        let w = self
+            .inverse
+            .iter()
+            .map(|row| row.get_bit(i))
+            .collect::<Vec<_>>();
+        x.set_bit(i, false);
+        for (inverse_row, _) in self.inverse.iter_mut().zip(w.iter()).filter(|(_, w)| **w) {
+            inverse_row.xor_into(&x);
+        }
+
+        // Only the equation for the new sample changed; fix it along w.
+        if sample.vector_product(&self.secret, k) != sample.get_product() {
+            for (row, _) in w.iter().enumerate().filter(|(_, w)| **w) {
+                self.secret.set_bit(row, !self.secret.get_bit(row));
+            }
+        }
+        debug_assert_eq!(sample.vector_product(&self.secret, k), sample.get_product());
+        true
+    }
+
+    /// The current solution with the product bit set, to compute ``A_m s' + b_m``.
+    fn extended_secret(&self) -> Sample<N> {
+        let mut secret = self.secret.clone();
+        secret.set_product(true);
+        secret
+    }
+}
+
+/// Compute the size ``m`` of the pool of test samples and the maximum weight ``c``
+/// of ``A_m s' + b_m`` for which we accept a candidate ``s'``.
+pub(crate) fn test_parameters(k: usize, tau: f64) -> (usize, u32) {
+    let alpha = 0.5f64.powi(k as i32);
+    let beta = ((1f64 - tau) / 2f64).powi(k as i32);
+    let m: f64 = (((1.5 * (1.0 / alpha).ln()).sqrt() + (1.0 / beta).ln().sqrt()) / (0.5 - tau))
+        .powi(2)
+        .floor();
+    let c = (tau * m + (3.0 * (0.5 - tau) * (1.0 / alpha).ln() * m).sqrt().floor()) as u32;
+    (m as usize, c)
 }
 
 /// Randomly sample ``k`` queries from the oracle as a ``(A, s)``.
-fn sample_matrix<'a>(k: usize, oracle: &LpnOracle, rng: &mut ThreadRng) -> (BinMatrix, BinMatrix) {
+pub(crate) fn sample_matrix<'a>(
+    k: usize,
+    oracle: &LpnOracle,
+    rng: &mut ThreadRng,
+) -> (BinMatrix, BinMatrix) {
     thread_local!(static TLS: RefCell<(Vec<&'static [StorageBlock]>, BinVector)> = RefCell::new((Vec::new(), BinVector::new())));
 
     TLS.with(|stor| {
         let mut stor = stor.borrow_mut();
         let (slices, b_bits) = &mut (*stor);
-        let samples = oracle.samples.choose_multiple(rng, k);
-        slices.extend(samples.map(|q| {
-            b_bits.push(q.get_product());
-            // we cheat the lifetime but this is okay, because we clear out `samples` at the end.
-            unsafe {
-                std::mem::transmute::<&'_ [StorageBlock], &'static [StorageBlock]>(q.get_sample())
-            }
-        }));
+        with_samples!(&oracle.samples, samples => {
+            slices.extend(samples.choose_multiple(rng, k).map(|q| {
+                b_bits.push(q.get_product());
+                // we cheat the lifetime but this is okay, because we clear out `samples` at the end.
+                unsafe {
+                    std::mem::transmute::<&'_ [StorageBlock], &'static [StorageBlock]>(
+                        q.get_sample(),
+                    )
+                }
+            }))
+        });
         // replace by matrix directly?
         let mat = BinMatrix::from_slices(slices, oracle.get_k());
         let ret_b = b_bits.as_column_matrix();
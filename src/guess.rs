@@ -0,0 +1,205 @@
+//! Guess-secret reduction, from Bogos, Tramèr and Vaudenay
+//!
+//! Fix a set of secret positions and enumerate low-weight assignments to them.
+//! Instead of running the remaining chain once per guess, all guesses share the
+//! reduced sample pool: the guessed positions are not reduced and only change
+//! the signs of the samples in the final FWHT.
+use crate::{
+    lf1::fwht,
+    oracle::{with_samples, LpnOracle},
+};
+use itertools::Itertools;
+use m4ri_rust::friendly::BinVector;
+use rayon::prelude::*;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+/// Gather the bits of `value` selected by `mask` into the low bits
+#[inline]
+fn compress_bits(value: u64, mut mask: u64) -> u64 {
+    let mut result = 0;
+    let mut bit = 0;
+    while mask != 0 {
+        let lowest = mask.trailing_zeros();
+        result |= ((value >> lowest) & 1) << bit;
+        bit += 1;
+        mask &= mask - 1;
+    }
+    result
+}
+
+/// Solve by guessing the secret bits at `positions` and using the FWHT on the others.
+///
+/// Assignments of weight at most `max_weight` are tried in order of likelihood,
+/// which for a sparse secret (`delta_s > 0`) means by increasing weight.
+/// We stop as soon as a guess gives a correlation that a wrong candidate is unlikely to reach,
+/// otherwise we return the best candidate over all guesses.
+///
+/// The samples are bucketed by their guessed and remaining bits, and the bucket of every
+/// guessed pattern is transformed once. This needs `2^k` counters and about the work of
+/// [`fwht_solve`](crate::lf1::fwht_solve) on all `k` bits. As the FWHT is linear, each guess
+/// then costs a signed sum of the transformed buckets, up to `2^k` additions.
+/// This only beats a single FWHT over `k` bits if the threshold stops us after a few guesses.
+///
+/// `$k' = k - t$` per guess
+pub fn guess_secret_solve(oracle: LpnOracle, positions: &[usize], max_weight: usize) -> BinVector {
+    let k = oracle.get_k();
+    let t = positions.len();
+    assert!(k < 40, "Don't use too large k! k = {}", k);
+    assert!(t > 0 && t < k, "Need to guess between 1 and k-1 bits");
+    assert!(
+        positions.iter().all(|&pos| pos < k) && positions.iter().all_unique(),
+        "Positions should be distinct and smaller than k"
+    );
+    if oracle.delta_s <= 0.0 {
+        log::warn!("The secret is not sparse, low-weight guesses are not the most likely");
+    }
+
+    let guess_mask = positions.iter().fold(0u64, |mask, &pos| mask | 1 << pos);
+    let rest_mask = ((1u64 << k) - 1) & !guess_mask;
+    let rest_bits = (k - t) as u32;
+    let rest_size = 1usize << rest_bits;
+    let n = oracle.samples.len();
+    assert!(n > 0, "What, no samples?");
+
+    log::info!(
+        "Guessing {} secret bits up to weight {}, FWHT on k' = {}",
+        t,
+        max_weight,
+        rest_bits
+    );
+
+    // bucket the samples as counters[pattern][rest]
+    let mut counters = Vec::new();
+    counters.resize_with(1usize << k, || AtomicI64::new(0));
+    with_samples!(&oracle.samples, samples => samples
+    .par_iter()
+    .for_each_with(&counters[..], |counters, sample| {
+        let value = sample.get_block(0);
+        let pattern = positions
+            .iter()
+            .enumerate()
+            .fold(0u64, |pattern, (j, &pos)| {
+                pattern | ((value >> pos) & 1) << j
+            });
+        let rest = compress_bits(value, rest_mask);
+        let idx = (pattern as usize) << rest_bits | rest as usize;
+        counters[idx].fetch_add(if sample.get_product() { -1 } else { 1 }, Ordering::Relaxed);
+    }));
+    let counters = counters
+        .into_iter()
+        .map(AtomicI64::into_inner)
+        .collect::<Vec<_>>();
+    let mut counters = counters;
+    let patterns = counters
+        .par_chunks_mut(rest_size)
+        .enumerate()
+        .filter(|(_, chunk)| chunk.iter().any(|&c| c != 0))
+        .map(|(pattern, chunk)| {
+            fwht(chunk, rest_bits);
+            (pattern as u64, &*chunk)
+        })
+        .collect::<Vec<_>>();
+    log::debug!("{} of {} guessed patterns occur", patterns.len(), 1 << t);
+
+    // a wrong candidate exceeds this with probability at most about 2^k * exp(-noise^2 / 2n),
+    // the correct one has correlation around n * delta.
+    let noise = (2.0 * n as f64 * (k as f64) * 2f64.ln()).sqrt();
+    let expected = n as f64 * oracle.delta;
+    let threshold = if expected > noise {
+        (noise + expected) / 2.0
+    } else {
+        f64::INFINITY
+    };
+
+    let mut table = vec![0i64; rest_size];
+    let mut best: Option<(i64, u64, usize)> = None;
+    let guesses = (0..=max_weight.min(t)).flat_map(|w| (0..t).combinations(w));
+    for guess in guesses {
+        let guess = guess.into_iter().fold(0u64, |g, j| g | 1 << j);
+        // flip the products of all samples with <a_guess, guess> = 1
+        let flipped = patterns
+            .iter()
+            .map(|(pattern, _)| (pattern & guess).count_ones() % 2 == 1)
+            .collect::<Vec<_>>();
+        table.par_iter_mut().enumerate().for_each(|(rest, c)| {
+            *c = patterns
+                .iter()
+                .zip(flipped.iter())
+                .map(|((_, spectrum), &flip)| {
+                    if flip {
+                        -spectrum[rest]
+                    } else {
+                        spectrum[rest]
+                    }
+                })
+                .sum();
+        });
+        let (rest, &value) = table
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, &value)| value)
+            .unwrap();
+
+        if best.is_none() || value > best.unwrap().0 {
+            log::trace!("New best guess {:b} with correlation {}", guess, value);
+            best = Some((value, guess, rest));
+        }
+        if value as f64 > threshold {
+            log::debug!("Guess {:b} exceeds threshold {:.1}", guess, threshold);
+            break;
+        }
+    }
+
+    let (value, guess, rest) = best.expect("Should have tried at least one guess");
+    log::info!(
+        "Best guess has weight {} and correlation {}",
+        guess.count_ones(),
+        value
+    );
+
+    // reassemble the secret
+    let mut result = BinVector::with_capacity(k);
+    let mut rest_idx = 0;
+    for i in 0..k {
+        if let Some(j) = positions.iter().position(|&pos| pos == i) {
+            result.push(guess >> j & 1 == 1);
+        } else {
+            result.push(rest >> rest_idx & 1 == 1);
+            rest_idx += 1;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{lf1::xor_reduce, oracle::Sample};
+
+    #[test]
+    fn compress() {
+        assert_eq!(compress_bits(0b1011_0110, 0b1111_0000), 0b1011);
+        assert_eq!(compress_bits(0b1011_0110, 0b0101_0101), 0b0110);
+    }
+
+    #[test]
+    fn run_guess() {
+        let mut secret = BinVector::from_elem(24, false);
+        secret.set(1, true);
+        secret.set(3, true);
+        secret.set(10, true);
+        secret.set(17, true);
+        let secret = Sample::from_binvector(&secret, false);
+        let mut oracle = LpnOracle::new_with_secret(secret, 24, 1.0 / 8.0);
+        oracle.get_samples(20_000);
+        let secret = oracle.secret.as_binvector(24);
+
+        // reduce the top bits, keep the guessed bits at the bottom
+        xor_reduce(&mut oracle, 6);
+        let solution = guess_secret_solve(oracle, &[0, 1, 2, 3, 4, 5], 2);
+        assert_eq!(
+            solution,
+            secret.as_matrix().get_window(0, 0, 1, 18).as_vector()
+        );
+    }
+}
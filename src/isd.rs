@@ -0,0 +1,349 @@
+//! Information-set decoding solvers for LPN
+//!
+//! These treat the pool of samples as a decoding problem of a random linear code.
+//! We pick `k` samples as the information set and allow a small number of errors in it,
+//! which we find by enumeration:
+//!
+//! * Prange: no errors in the information set. This is [`pooled_gauss_solve`](crate::gauss::pooled_gauss_solve).
+//! * Stern/Dumer: `p` errors, split evenly over the two halves of the information set
+//!   and found by collision on `l` additional samples.
+//! * MMT: `p` errors, found using representations of the error as a sum of two
+//!   vectors of weight `p/2` (May, Meurer, Thomae; "well-pooled" variant by Esser, Kübler and May).
+//!
+//! Candidates are checked using the same hypothesis test as Pooled Gauss.
+use crate::{
+    gauss::{sample_matrix, test_parameters},
+    oracle::{LpnOracle, SampleStorage, SAMPLE_LEN},
+    random::{lpn_thread_rng, ThreadRng},
+};
+use fnv::FnvHashMap;
+use itertools::Itertools;
+use m4ri_rust::friendly::BinVector;
+use rand::Rng;
+use rayon::prelude::*;
+use std::{
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+/// The variant of information-set decoding to use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsdVariant {
+    /// Plain Prange: an error-free information set
+    Prange,
+    /// Stern/Dumer with `p` errors in the information set and a collision on `l` bits
+    Stern { p: usize, l: usize },
+    /// MMT with `p` errors in the information set, an intermediate collision on `l1` bits
+    /// and a final collision on `l` bits
+    Mmt { p: usize, l1: usize, l: usize },
+}
+
+/// The largest collision window we support, as collisions are computed on a `u64`
+const MAX_L: usize = 48;
+
+/// Natural logarithm of the binomial coefficient
+fn ln_binomial(n: usize, k: usize) -> f64 {
+    if k > n {
+        return f64::NEG_INFINITY;
+    }
+    (0..k).fold(0.0, |acc, i| {
+        acc + ((n - i) as f64).ln() - ((i + 1) as f64).ln()
+    })
+}
+
+/// Estimate the natural logarithm of the expected number of bit operations of a variant.
+fn ln_expected_cost(k: usize, tau: f64, variant: IsdVariant) -> f64 {
+    let (m, _) = test_parameters(k, tau);
+    let k_f = k as f64;
+    let ln_gauss = (k_f.powi(3) / 64.0).ln();
+    let ln_test = (m as f64 * k_f / 64.0).ln();
+    let ln_ok = |errors_outside: usize| errors_outside as f64 * (1.0 - tau).ln();
+    let ln_add = |a: f64, b: f64| a.max(b) + (1.0 + (-(a - b).abs()).exp()).ln();
+    let half = k / 2;
+
+    match variant {
+        IsdVariant::Prange => ln_add(ln_gauss, ln_test) - ln_ok(k),
+        IsdVariant::Stern { p, l } => {
+            let ln_list = ln_binomial(half, p / 2);
+            let ln_candidates = 2.0 * ln_list - l as f64 * 2f64.ln();
+            let ln_iteration = ln_add(ln_add(ln_gauss, ln_list), ln_candidates + ln_test);
+            let ln_success = 2.0 * ln_list + p as f64 * tau.ln() + ln_ok(k - p + l);
+            ln_iteration - ln_success
+        }
+        IsdVariant::Mmt { p, l1, l } => {
+            let ln_base = ln_binomial(half, p / 4);
+            let ln_level1 = 2.0 * ln_base - l1 as f64 * 2f64.ln();
+            let ln_candidates = 2.0 * ln_level1 - (l - l1) as f64 * 2f64.ln();
+            let ln_iteration = ln_add(
+                ln_add(ln_gauss, ln_base),
+                ln_add(ln_level1, ln_candidates + ln_test),
+            );
+            let ln_representations = 2.0 * ln_binomial(p / 2, p / 4);
+            let ln_found = (ln_representations - l1 as f64 * 2f64.ln()).min(0.0);
+            let ln_success =
+                2.0 * ln_binomial(half, p / 2) + p as f64 * tau.ln() + ln_ok(k - p + l) + ln_found;
+            ln_iteration - ln_success
+        }
+    }
+}
+
+/// Select the variant and parameters with the lowest estimated cost
+pub fn select_isd_variant(k: usize, tau: f64) -> IsdVariant {
+    let half = k / 2;
+    let max_l = std::cmp::min(MAX_L, k);
+    let sterns = (2..=8)
+        .step_by(2)
+        .filter(|p| p / 2 <= half)
+        .flat_map(|p| (0..=max_l).map(move |l| IsdVariant::Stern { p, l }));
+    let mmts = (4..=8).step_by(4).filter(|p| p / 4 <= half).flat_map(|p| {
+        (1..=max_l).flat_map(move |l| (1..=l).map(move |l1| IsdVariant::Mmt { p, l1, l }))
+    });
+
+    let (best, cost) = std::iter::once(IsdVariant::Prange)
+        .chain(sterns)
+        .chain(mmts)
+        .map(|variant| (variant, ln_expected_cost(k, tau, variant)))
+        .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+        .unwrap();
+    log::info!(
+        "Selected {:?} with estimated cost 2^{:.2} (Pooled Gauss: 2^{:.2})",
+        best,
+        cost / 2f64.ln(),
+        ln_expected_cost(k, tau, IsdVariant::Prange) / 2f64.ln()
+    );
+    best
+}
+
+/// Solve using information-set decoding with automatically selected parameters
+pub fn isd_solve(oracle: LpnOracle) -> BinVector {
+    let tau = (1.0 - oracle.delta) / 2.0;
+    let variant = select_isd_variant(oracle.get_k(), tau);
+    isd_solve_with(oracle, variant)
+}
+
+/// Solve using Prange's information-set decoding
+pub fn prange_solve(oracle: LpnOracle) -> BinVector {
+    isd_solve_with(oracle, IsdVariant::Prange)
+}
+
+/// Solve using Stern/Dumer information-set decoding
+pub fn stern_solve(oracle: LpnOracle, p: usize, l: usize) -> BinVector {
+    isd_solve_with(oracle, IsdVariant::Stern { p, l })
+}
+
+/// Solve using MMT information-set decoding
+pub fn mmt_solve(oracle: LpnOracle, p: usize, l1: usize, l: usize) -> BinVector {
+    isd_solve_with(oracle, IsdVariant::Mmt { p, l1, l })
+}
+
+/// Solve using the given variant of information-set decoding
+#[allow(clippy::needless_pass_by_value)]
+pub fn isd_solve_with(oracle: LpnOracle, variant: IsdVariant) -> BinVector {
+    let k = oracle.get_k();
+    match variant {
+        IsdVariant::Prange => (),
+        IsdVariant::Stern { p, l } => {
+            assert!(p % 2 == 0, "Stern requires even p");
+            assert!(l <= MAX_L, "Collision window too large");
+        }
+        IsdVariant::Mmt { p, l1, l } => {
+            assert!(p % 4 == 0, "MMT requires p divisible by 4");
+            assert!(l1 <= l && l <= MAX_L, "Invalid collision windows");
+        }
+    }
+    let tau = (1.0 - oracle.delta) / 2.0;
+    let (m, c) = test_parameters(k, tau);
+    log::info!(
+        "Attempting {:?} ISD solving method, k={}, tau={}",
+        variant,
+        k,
+        tau
+    );
+    log::trace!("Building (Am, b) with length {}, target weight <= {}", m, c);
+    let (am, bm) = sample_matrix(m, &oracle, &mut lpn_thread_rng());
+
+    let test = |s_prime: &BinVector| {
+        let mut testproduct = &am * &s_prime.as_column_matrix();
+        testproduct += &bm;
+        testproduct.count_ones() <= c
+    };
+
+    let finder = |(found, rng): &mut (Arc<Mutex<Option<BinVector>>>, ThreadRng), _| {
+        for _ in 0..100 {
+            if let Some(secret) = isd_iteration(&oracle, variant, &test, rng) {
+                found.lock().unwrap().replace(secret);
+                return None;
+            }
+            if found.lock().unwrap().is_some() {
+                return None;
+            }
+        }
+        Some(())
+    };
+
+    let found_parent = Arc::new(Mutex::new(None));
+    let found = found_parent.clone();
+    rayon::iter::repeat(()).try_for_each_init(|| (found.clone(), lpn_thread_rng()), finder);
+
+    let found = found_parent.lock().unwrap();
+    found.as_ref().unwrap().clone()
+}
+
+/// Enumerate all error patterns of weight `weight` on `range`
+/// together with their contributions to the syndrome.
+fn enumerate_errors(q: &[u64], range: Range<usize>, weight: usize) -> Vec<(u64, SampleStorage)> {
+    range
+        .combinations(weight)
+        .map(|idxs| {
+            let mut errors = [0; SAMPLE_LEN];
+            let mut syndrome = 0;
+            for j in idxs {
+                errors[j / 64] |= 1 << (j % 64);
+                syndrome ^= q[j];
+            }
+            (syndrome, errors)
+        })
+        .collect()
+}
+
+/// Find all sums of elements of `left` and `right` with syndrome `target` on the bits in `mask`
+fn merge_lists(
+    left: &[(u64, SampleStorage)],
+    right: &[(u64, SampleStorage)],
+    mask: u64,
+    target: u64,
+) -> Vec<(u64, SampleStorage)> {
+    let mut table: FnvHashMap<u64, Vec<usize>> = FnvHashMap::default();
+    for (idx, (syndrome, _)) in left.iter().enumerate() {
+        table.entry(syndrome & mask).or_default().push(idx);
+    }
+    let mut result = Vec::new();
+    for (syndrome, errors) in right {
+        if let Some(idxs) = table.get(&((syndrome ^ target) & mask)) {
+            for &idx in idxs {
+                let (other_syndrome, other_errors) = &left[idx];
+                let mut sum = *errors;
+                sum.iter_mut()
+                    .zip(other_errors.iter())
+                    .for_each(|(a, b)| *a ^= b);
+                result.push((syndrome ^ other_syndrome, sum));
+            }
+        }
+    }
+    result
+}
+
+/// Run a single iteration: draw an information set and enumerate the error patterns on it.
+fn isd_iteration<F: Fn(&BinVector) -> bool>(
+    oracle: &LpnOracle,
+    variant: IsdVariant,
+    test: &F,
+    rng: &mut ThreadRng,
+) -> Option<BinVector> {
+    let k = oracle.get_k();
+    let l = match variant {
+        IsdVariant::Prange => 0,
+        IsdVariant::Stern { l, .. } | IsdVariant::Mmt { l, .. } => l,
+    };
+
+    // find information set of rank k
+    let (a, b) = sample_matrix(k + l, oracle, rng);
+    let a_info = a.get_window(0, 0, k, k);
+    if a_info.clone().echelonize() != k {
+        return None;
+    }
+    let b_info = b.get_window(0, 0, k, 1);
+    let a_info_inv = a_info.inverted();
+    // s = A_I^{-1} (b_I + e_I)
+    let s_zero = (&a_info_inv * &b_info).as_vector();
+    if let IsdVariant::Prange = variant {
+        return if test(&s_zero) { Some(s_zero) } else { None };
+    }
+
+    // Q e_I = b_L + Q b_I with Q = A_L A_I^{-1}, if the samples in L are error-free.
+    let columns = a_info_inv.transposed();
+    let (q, syndrome): (Vec<u64>, u64) = if l > 0 {
+        let a_coll = a.get_window(k, 0, k + l, k);
+        let b_coll = b.get_window(k, 0, k + l, 1);
+        let q_matrix = &a_coll * &a_info_inv;
+        let mut syndrome = &q_matrix * &b_info;
+        syndrome += &b_coll;
+        let q_transposed = q_matrix.transposed();
+        (
+            (0..k)
+                .map(|j| q_transposed.get_window(j, 0, j + 1, l).as_vector().as_u64())
+                .collect(),
+            syndrome.as_vector().as_u64(),
+        )
+    } else {
+        (vec![0; k], 0)
+    };
+    let full_mask = (1 << l) - 1;
+
+    let candidates = match variant {
+        IsdVariant::Prange => unreachable!(),
+        IsdVariant::Stern { p, .. } => {
+            let left = enumerate_errors(&q, 0..k / 2, p / 2);
+            let right = enumerate_errors(&q, k / 2..k, p / 2);
+            merge_lists(&left, &right, full_mask, syndrome)
+        }
+        IsdVariant::Mmt { p, l1, .. } => {
+            let left = enumerate_errors(&q, 0..k / 2, p / 4);
+            let right = enumerate_errors(&q, k / 2..k, p / 4);
+            let mask = (1 << l1) - 1;
+            let target = rng.gen::<u64>() & mask;
+            let first = merge_lists(&left, &right, mask, target);
+            let second = merge_lists(&left, &right, mask, syndrome ^ target);
+            merge_lists(&first, &second, full_mask, syndrome)
+                .into_iter()
+                .filter(|(_, errors)| {
+                    errors.iter().map(|b| b.count_ones()).sum::<u32>() as usize == p
+                })
+                .collect()
+        }
+    };
+
+    for (_, errors) in candidates {
+        let mut s_prime = s_zero.clone();
+        for j in 0..k {
+            if (errors[j / 64] >> (j % 64)) & 1 == 1 {
+                s_prime += &columns.get_window(j, 0, j + 1, k).as_vector();
+            }
+        }
+        if test(&s_prime) {
+            return Some(s_prime);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn run_stern() {
+        let mut oracle: LpnOracle = LpnOracle::new(32, 1.0 / 8.0);
+        oracle.get_samples(100_000);
+        let secret = oracle.secret.as_binvector(32);
+        let solution = stern_solve(oracle, 2, 8);
+        assert_eq!(solution, secret);
+    }
+
+    #[test]
+    fn run_mmt() {
+        let mut oracle: LpnOracle = LpnOracle::new(32, 1.0 / 8.0);
+        oracle.get_samples(100_000);
+        let secret = oracle.secret.as_binvector(32);
+        let solution = mmt_solve(oracle, 4, 4, 12);
+        assert_eq!(solution, secret);
+    }
+
+    #[test]
+    fn run_isd() {
+        let mut oracle: LpnOracle = LpnOracle::new(32, 1.0 / 8.0);
+        oracle.get_samples(100_000);
+        let secret = oracle.secret.as_binvector(32);
+        assert_eq!(isd_solve(oracle), secret);
+    }
+}
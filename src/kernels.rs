@@ -0,0 +1,493 @@
+//! Kernels on bit vectors and counters, with runtime CPU feature dispatch
+//!
+//! Every kernel has a portable scalar version that works on any target.
+//! On x86-64 we detect once which instruction sets the CPU supports and use AVX2 or AVX-512
+//! versions instead, which are also compiled with the `popcnt` instruction.
+//! Short inputs, such as a single sample, always use the scalar versions:
+//! there the dispatch costs more than it gains.
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// The instruction sets the kernels can use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// Portable code, for any target
+    Scalar,
+    /// AVX2 and `popcnt`
+    Avx2,
+    /// AVX-512F, AVX-512 VPOPCNTDQ and `popcnt`
+    Avx512,
+}
+
+const UNDETECTED: u8 = u8::MAX;
+static BACKEND: AtomicU8 = AtomicU8::new(UNDETECTED);
+
+/// Inputs shorter than this use the scalar kernels
+const SIMD_MIN_LEN: usize = 8;
+
+impl Backend {
+    /// The fastest backend this CPU supports
+    pub fn detect() -> Backend {
+        if Backend::Avx512.is_supported() {
+            Backend::Avx512
+        } else if Backend::Avx2.is_supported() {
+            Backend::Avx2
+        } else {
+            Backend::Scalar
+        }
+    }
+
+    /// Whether this CPU can run this backend
+    pub fn is_supported(self) -> bool {
+        match self {
+            Backend::Scalar => true,
+            #[cfg(target_arch = "x86_64")]
+            Backend::Avx2 => is_x86_feature_detected!("avx2") && is_x86_feature_detected!("popcnt"),
+            #[cfg(target_arch = "x86_64")]
+            Backend::Avx512 => {
+                is_x86_feature_detected!("avx512f")
+                    && is_x86_feature_detected!("avx512vpopcntdq")
+                    && is_x86_feature_detected!("popcnt")
+            }
+            #[cfg(not(target_arch = "x86_64"))]
+            _ => false,
+        }
+    }
+}
+
+/// The backend used by the kernels, detected on first use
+pub fn backend() -> Backend {
+    match BACKEND.load(Ordering::Relaxed) {
+        0 => Backend::Scalar,
+        1 => Backend::Avx2,
+        2 => Backend::Avx512,
+        _ => {
+            let backend = Backend::detect();
+            log::debug!("Using {:?} kernels", backend);
+            set_backend(backend);
+            backend
+        }
+    }
+}
+
+/// Override the backend used by the kernels, for example to compare them
+pub fn set_backend(backend: Backend) {
+    assert!(
+        backend.is_supported(),
+        "This CPU does not support {:?}",
+        backend
+    );
+    BACKEND.store(backend as u8, Ordering::Relaxed);
+}
+
+/// The backend to use for an input of `len` elements
+#[inline]
+fn backend_for(len: usize) -> Backend {
+    if len < SIMD_MIN_LEN {
+        Backend::Scalar
+    } else {
+        backend()
+    }
+}
+
+/// `dst ^= src`
+#[inline]
+pub fn xor_into(dst: &mut [u64], src: &[u64]) {
+    assert_eq!(dst.len(), src.len());
+    match backend_for(dst.len()) {
+        #[cfg(target_arch = "x86_64")]
+        Backend::Avx2 => unsafe { avx2::xor_into(dst, src) },
+        #[cfg(target_arch = "x86_64")]
+        Backend::Avx512 => unsafe { avx512::xor_into(dst, src) },
+        _ => scalar::xor_into(dst, src),
+    }
+}
+
+/// The parity of `a & b`, the inner product of two bit vectors
+#[inline]
+pub fn and_parity(a: &[u64], b: &[u64]) -> bool {
+    assert_eq!(a.len(), b.len());
+    match backend_for(a.len()) {
+        #[cfg(target_arch = "x86_64")]
+        Backend::Avx2 => unsafe { avx2::and_parity(a, b) },
+        #[cfg(target_arch = "x86_64")]
+        Backend::Avx512 => unsafe { avx512::and_parity(a, b) },
+        _ => scalar::and_parity(a, b),
+    }
+}
+
+/// The Hamming weight of a bit vector
+#[inline]
+pub fn count_ones(data: &[u64]) -> u64 {
+    match backend_for(data.len()) {
+        #[cfg(target_arch = "x86_64")]
+        Backend::Avx2 => unsafe { avx2::count_ones(data) },
+        #[cfg(target_arch = "x86_64")]
+        Backend::Avx512 => unsafe { avx512::count_ones(data) },
+        _ => scalar::count_ones(data),
+    }
+}
+
+/// The butterfly of the Walsh-Hadamard transform: `(l, r) = (l + r, l - r)`
+#[inline]
+pub fn butterfly(left: &mut [i64], right: &mut [i64]) {
+    assert_eq!(left.len(), right.len());
+    match backend_for(left.len()) {
+        #[cfg(target_arch = "x86_64")]
+        Backend::Avx2 => unsafe { avx2::butterfly(left, right) },
+        #[cfg(target_arch = "x86_64")]
+        Backend::Avx512 => unsafe { avx512::butterfly(left, right) },
+        _ => scalar::butterfly(left, right),
+    }
+}
+
+/// The inner products of the rows of `rows` with `vector`
+///
+/// `rows` holds `out.len()` rows of `vector.len()` words each.
+pub fn inner_products(rows: &[u64], vector: &[u64], out: &mut [bool]) {
+    assert_eq!(rows.len(), vector.len() * out.len());
+    match backend_for(rows.len()) {
+        #[cfg(target_arch = "x86_64")]
+        Backend::Avx2 => unsafe { avx2::inner_products(rows, vector, out) },
+        #[cfg(target_arch = "x86_64")]
+        Backend::Avx512 => unsafe { avx512::inner_products(rows, vector, out) },
+        _ => scalar::inner_products(rows, vector, out),
+    }
+}
+
+mod scalar {
+    #[inline(always)]
+    pub(super) fn xor_into(dst: &mut [u64], src: &[u64]) {
+        dst.iter_mut().zip(src).for_each(|(d, s)| *d ^= s);
+    }
+
+    #[inline(always)]
+    pub(super) fn and_parity(a: &[u64], b: &[u64]) -> bool {
+        a.iter()
+            .zip(b)
+            .fold(0, |acc, (a, b)| acc ^ (a & b))
+            .count_ones()
+            % 2
+            == 1
+    }
+
+    #[inline(always)]
+    pub(super) fn count_ones(data: &[u64]) -> u64 {
+        data.iter().map(|x| x.count_ones() as u64).sum()
+    }
+
+    #[inline(always)]
+    pub(super) fn butterfly(left: &mut [i64], right: &mut [i64]) {
+        left.iter_mut().zip(right.iter_mut()).for_each(|(l, r)| {
+            let (a, b) = (*l, *r);
+            *l = a + b;
+            *r = a - b;
+        });
+    }
+
+    #[inline(always)]
+    pub(super) fn inner_products(rows: &[u64], vector: &[u64], out: &mut [bool]) {
+        rows.chunks_exact(vector.len())
+            .zip(out.iter_mut())
+            .for_each(|(row, out)| *out = and_parity(row, vector));
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use super::scalar;
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn xor_into(dst: &mut [u64], src: &[u64]) {
+        let chunks = dst.len() / 4;
+        for i in 0..chunks {
+            let d = dst.as_mut_ptr().add(4 * i) as *mut __m256i;
+            let s = src.as_ptr().add(4 * i) as *const __m256i;
+            _mm256_storeu_si256(
+                d,
+                _mm256_xor_si256(_mm256_loadu_si256(d), _mm256_loadu_si256(s)),
+            );
+        }
+        scalar::xor_into(&mut dst[4 * chunks..], &src[4 * chunks..]);
+    }
+
+    /// The Hamming weight of every 64-bit lane, with a lookup table of the nibbles
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn popcount_lanes(x: __m256i) -> __m256i {
+        let lookup = _mm256_setr_epi8(
+            0, 1, 1, 2, 1, 2, 2, 3, 1, 2, 2, 3, 2, 3, 3, 4, 0, 1, 1, 2, 1, 2, 2, 3, 1, 2, 2, 3, 2,
+            3, 3, 4,
+        );
+        let low_mask = _mm256_set1_epi8(0x0f);
+        let low = _mm256_and_si256(x, low_mask);
+        let high = _mm256_and_si256(_mm256_srli_epi16(x, 4), low_mask);
+        let counts = _mm256_add_epi8(
+            _mm256_shuffle_epi8(lookup, low),
+            _mm256_shuffle_epi8(lookup, high),
+        );
+        _mm256_sad_epu8(counts, _mm256_setzero_si256())
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn lanes(x: __m256i) -> [u64; 4] {
+        let mut lanes = [0u64; 4];
+        _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, x);
+        lanes
+    }
+
+    #[target_feature(enable = "avx2,popcnt")]
+    pub(super) unsafe fn and_parity(a: &[u64], b: &[u64]) -> bool {
+        let chunks = a.len() / 4;
+        let mut acc = _mm256_setzero_si256();
+        for i in 0..chunks {
+            let x = _mm256_loadu_si256(a.as_ptr().add(4 * i) as *const __m256i);
+            let y = _mm256_loadu_si256(b.as_ptr().add(4 * i) as *const __m256i);
+            acc = _mm256_xor_si256(acc, _mm256_and_si256(x, y));
+        }
+        let tail = a[4 * chunks..]
+            .iter()
+            .zip(&b[4 * chunks..])
+            .fold(0, |acc, (a, b)| acc ^ (a & b));
+        lanes(acc)
+            .iter()
+            .fold(tail, |acc, lane| acc ^ lane)
+            .count_ones()
+            % 2
+            == 1
+    }
+
+    #[target_feature(enable = "avx2,popcnt")]
+    pub(super) unsafe fn count_ones(data: &[u64]) -> u64 {
+        let chunks = data.len() / 4;
+        let mut total = _mm256_setzero_si256();
+        for i in 0..chunks {
+            let x = _mm256_loadu_si256(data.as_ptr().add(4 * i) as *const __m256i);
+            total = _mm256_add_epi64(total, popcount_lanes(x));
+        }
+        lanes(total).iter().sum::<u64>() + scalar::count_ones(&data[4 * chunks..])
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn butterfly(left: &mut [i64], right: &mut [i64]) {
+        let chunks = left.len() / 4;
+        for i in 0..chunks {
+            let l = left.as_mut_ptr().add(4 * i) as *mut __m256i;
+            let r = right.as_mut_ptr().add(4 * i) as *mut __m256i;
+            let (a, b) = (_mm256_loadu_si256(l), _mm256_loadu_si256(r));
+            _mm256_storeu_si256(l, _mm256_add_epi64(a, b));
+            _mm256_storeu_si256(r, _mm256_sub_epi64(a, b));
+        }
+        scalar::butterfly(&mut left[4 * chunks..], &mut right[4 * chunks..]);
+    }
+
+    /// Rows that fit in a register several times are done together,
+    /// longer rows one at a time with [`and_parity`].
+    #[target_feature(enable = "avx2,popcnt")]
+    pub(super) unsafe fn inner_products(rows: &[u64], vector: &[u64], out: &mut [bool]) {
+        let width = vector.len();
+        if 4 % width != 0 {
+            for (row, out) in rows.chunks_exact(width).zip(out.iter_mut()) {
+                *out = and_parity(row, vector);
+            }
+            return;
+        }
+        let per_register = 4 / width;
+        let mut pattern = [0u64; 4];
+        pattern
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, word)| *word = vector[i % width]);
+        let pattern = _mm256_loadu_si256(pattern.as_ptr() as *const __m256i);
+        let chunks = out.len() / per_register;
+        for i in 0..chunks {
+            let x = _mm256_loadu_si256(rows.as_ptr().add(4 * i) as *const __m256i);
+            let counts = lanes(popcount_lanes(_mm256_and_si256(x, pattern)));
+            for (row, counts) in counts.chunks_exact(width).enumerate() {
+                out[i * per_register + row] = counts.iter().sum::<u64>() % 2 == 1;
+            }
+        }
+        scalar::inner_products(
+            &rows[4 * chunks..],
+            vector,
+            &mut out[per_register * chunks..],
+        );
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx512 {
+    use super::scalar;
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "avx512f")]
+    pub(super) unsafe fn xor_into(dst: &mut [u64], src: &[u64]) {
+        let chunks = dst.len() / 8;
+        for i in 0..chunks {
+            let d = dst.as_mut_ptr().add(8 * i);
+            let s = src.as_ptr().add(8 * i);
+            let x = _mm512_xor_si512(
+                _mm512_loadu_si512(d as *const _),
+                _mm512_loadu_si512(s as *const _),
+            );
+            _mm512_storeu_si512(d as *mut _, x);
+        }
+        scalar::xor_into(&mut dst[8 * chunks..], &src[8 * chunks..]);
+    }
+
+    #[target_feature(enable = "avx512f,avx512vpopcntdq,popcnt")]
+    pub(super) unsafe fn and_parity(a: &[u64], b: &[u64]) -> bool {
+        let chunks = a.len() / 8;
+        let mut acc = _mm512_setzero_si512();
+        for i in 0..chunks {
+            let x = _mm512_loadu_si512(a.as_ptr().add(8 * i) as *const _);
+            let y = _mm512_loadu_si512(b.as_ptr().add(8 * i) as *const _);
+            acc = _mm512_xor_si512(acc, _mm512_and_si512(x, y));
+        }
+        let tail = a[8 * chunks..]
+            .iter()
+            .zip(&b[8 * chunks..])
+            .fold(0u64, |acc, (a, b)| acc ^ (a & b));
+        (_mm512_reduce_add_epi64(_mm512_popcnt_epi64(acc)) as u64 + tail.count_ones() as u64) % 2
+            == 1
+    }
+
+    #[target_feature(enable = "avx512f,avx512vpopcntdq,popcnt")]
+    pub(super) unsafe fn count_ones(data: &[u64]) -> u64 {
+        let chunks = data.len() / 8;
+        let mut total = _mm512_setzero_si512();
+        for i in 0..chunks {
+            let x = _mm512_loadu_si512(data.as_ptr().add(8 * i) as *const _);
+            total = _mm512_add_epi64(total, _mm512_popcnt_epi64(x));
+        }
+        _mm512_reduce_add_epi64(total) as u64 + scalar::count_ones(&data[8 * chunks..])
+    }
+
+    #[target_feature(enable = "avx512f")]
+    pub(super) unsafe fn butterfly(left: &mut [i64], right: &mut [i64]) {
+        let chunks = left.len() / 8;
+        for i in 0..chunks {
+            let l = left.as_mut_ptr().add(8 * i);
+            let r = right.as_mut_ptr().add(8 * i);
+            let (a, b) = (
+                _mm512_loadu_si512(l as *const _),
+                _mm512_loadu_si512(r as *const _),
+            );
+            _mm512_storeu_si512(l as *mut _, _mm512_add_epi64(a, b));
+            _mm512_storeu_si512(r as *mut _, _mm512_sub_epi64(a, b));
+        }
+        scalar::butterfly(&mut left[8 * chunks..], &mut right[8 * chunks..]);
+    }
+
+    /// Rows that fit in a register several times are done together,
+    /// longer rows one at a time with [`and_parity`].
+    #[target_feature(enable = "avx512f,avx512vpopcntdq,popcnt")]
+    pub(super) unsafe fn inner_products(rows: &[u64], vector: &[u64], out: &mut [bool]) {
+        let width = vector.len();
+        if 8 % width != 0 {
+            for (row, out) in rows.chunks_exact(width).zip(out.iter_mut()) {
+                *out = and_parity(row, vector);
+            }
+            return;
+        }
+        let per_register = 8 / width;
+        let mut pattern = [0u64; 8];
+        pattern
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, word)| *word = vector[i % width]);
+        let pattern = _mm512_loadu_si512(pattern.as_ptr() as *const _);
+        let chunks = out.len() / per_register;
+        let mut counts = [0u64; 8];
+        for i in 0..chunks {
+            let x = _mm512_loadu_si512(rows.as_ptr().add(8 * i) as *const _);
+            let x = _mm512_popcnt_epi64(_mm512_and_si512(x, pattern));
+            _mm512_storeu_si512(counts.as_mut_ptr() as *mut _, x);
+            for (row, counts) in counts.chunks_exact(width).enumerate() {
+                out[i * per_register + row] = counts.iter().sum::<u64>() % 2 == 1;
+            }
+        }
+        scalar::inner_products(
+            &rows[8 * chunks..],
+            vector,
+            &mut out[per_register * chunks..],
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::prelude::*;
+
+    #[test]
+    fn kernels_match_scalar() {
+        let rng = &mut rand::thread_rng();
+        let backends = [Backend::Scalar, Backend::Avx2, Backend::Avx512];
+        for backend in backends.iter().filter(|backend| backend.is_supported()) {
+            set_backend(*backend);
+            for len in [3, 8, 37, 1000] {
+                let a = (0..len).map(|_| rng.gen()).collect::<Vec<u64>>();
+                let b = (0..len).map(|_| rng.gen()).collect::<Vec<u64>>();
+
+                let mut xored = a.clone();
+                xor_into(&mut xored, &b);
+                let mut expected = a.clone();
+                scalar::xor_into(&mut expected, &b);
+                assert_eq!(xored, expected, "xor_into {:?} len {}", backend, len);
+
+                assert_eq!(
+                    and_parity(&a, &b),
+                    scalar::and_parity(&a, &b),
+                    "and_parity {:?} len {}",
+                    backend,
+                    len
+                );
+                assert_eq!(
+                    count_ones(&a),
+                    a.iter().map(|x| x.count_ones() as u64).sum::<u64>(),
+                    "count_ones {:?} len {}",
+                    backend,
+                    len
+                );
+
+                let counters = (0..2 * len)
+                    .map(|_| rng.gen::<i32>() as i64)
+                    .collect::<Vec<_>>();
+                let mut transformed = counters.clone();
+                let (left, right) = transformed.split_at_mut(len);
+                butterfly(left, right);
+                let mut expected = counters.clone();
+                let (left, right) = expected.split_at_mut(len);
+                scalar::butterfly(left, right);
+                assert_eq!(transformed, expected, "butterfly {:?} len {}", backend, len);
+
+                for width in [1, 2, 3, 4, 8] {
+                    let rows = (0..width * len).map(|_| rng.gen()).collect::<Vec<u64>>();
+                    let vector = &b[..width.min(len)];
+                    let rows = &rows[..vector.len() * len];
+                    let mut products = vec![false; len];
+                    inner_products(rows, vector, &mut products);
+                    let expected = rows
+                        .chunks_exact(vector.len())
+                        .map(|row| {
+                            row.iter()
+                                .zip(vector)
+                                .map(|(r, v)| (r & v).count_ones())
+                                .sum::<u32>()
+                                % 2
+                                == 1
+                        })
+                        .collect::<Vec<_>>();
+                    assert_eq!(
+                        products, expected,
+                        "inner_products {:?} len {} width {}",
+                        backend, len, width
+                    );
+                }
+            }
+        }
+        set_backend(Backend::detect());
+    }
+}
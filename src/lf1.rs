@@ -1,17 +1,20 @@
 //! Defines the algorithms from the Levieil and Fouque paper (LF1, LF2)
 use crate::{
     bkw::{create_partitions, create_pivots},
-    oracle::{are_last_bits_zero, query_bits_range, LpnOracle, Sample, SampleStorage},
+    kernels,
+    oracle::{are_last_bits_zero, query_bits_range, with_samples, LpnOracle, Sample},
+    random::lpn_thread_rng,
+    stream::SampleStream,
     util::log_2,
 };
 use itertools::Itertools;
 use m4ri_rust::friendly::BinMatrix;
 use m4ri_rust::friendly::BinVector;
-use packed_simd_2::i64x4;
+use rand::Rng;
 use rayon::prelude::*;
 use std::{
-    ops,
-    sync::atomic::{AtomicI64, Ordering},
+    fmt, ops,
+    sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering},
 };
 use unchecked_unwrap::UncheckedUnwrap;
 
@@ -28,6 +31,33 @@ fn usize_to_binmatrix(c: u64, size: usize) -> BinMatrix {
 ///
 /// Use the vector-matrix product version of the fwht
 pub fn lf1_solve(oracle: LpnOracle) -> BinVector {
+    let (b, computation) = lf1_correlation(oracle);
+
+    log::trace!("Doing LF1 naively");
+    let max = 2usize.pow(b as u32);
+    // find the candidate with the best weight
+    let best_candidate = (1..max)
+        .into_par_iter()
+        .max_by_key(|candidate| computation(*candidate))
+        .expect("Can't work on an empty list");
+
+    log::info!("Best candidate weight: {}", best_candidate.count_ones());
+    usize_to_binmatrix(best_candidate as u64, b).as_vector()
+}
+
+/// Recover the secret through LF1, ranking candidates by their posterior log-likelihood
+///
+/// See [`fwht_solve_with_prior`].
+pub fn lf1_solve_with_prior(oracle: LpnOracle, prior: &SecretPrior) -> BinVector {
+    let delta = oracle.delta;
+    let (b, computation) = lf1_correlation(oracle);
+    let best_candidate =
+        posterior_argmax(b, delta, prior, |candidate| computation(candidate) as i64);
+    usize_to_binmatrix(best_candidate as u64, b).as_vector()
+}
+
+/// Set up the computation of the correlation `n - 2 wt(A x + c)` of candidates `x` for LF1
+fn lf1_correlation(oracle: LpnOracle) -> (usize, impl Fn(usize) -> i32 + Sync) {
     // get the (a, c) samples as matrix A and vector c
     let n_prime = oracle.samples.len();
     assert!(n_prime > 0, "What, no samples?");
@@ -41,14 +71,13 @@ pub fn lf1_solve(oracle: LpnOracle) -> BinVector {
         let mut c = BinVector::with_capacity(n_prime);
         (
             BinMatrix::from_slices(
-                &oracle
-                    .samples
-                    .into_iter()
+                &with_samples!(&oracle.samples, samples => samples
+                    .iter()
                     .map(|q| {
                         c.push(q.get_product());
-                        q.into_inner()
+                        q.get_sample()
                     })
-                    .collect::<Vec<SampleStorage>>(),
+                    .collect::<Vec<_>>()),
                 b,
             ),
             c,
@@ -61,7 +90,7 @@ pub fn lf1_solve(oracle: LpnOracle) -> BinVector {
     drop(a_matrix);
 
     // LF1 query weight computation
-    let computation = |candidate: usize| {
+    let computation = move |candidate: usize| {
         // A u32 is 4 u8s.
         let candidate_vector = usize_to_binmatrix(candidate as u64, b);
 
@@ -70,17 +99,96 @@ pub fn lf1_solve(oracle: LpnOracle) -> BinVector {
         let hw = matrix_vector_product.count_ones();
         n_prime as i32 - 2 * (hw as i32)
     };
+    (b, computation)
+}
 
-    log::trace!("Doing LF1 naively");
-    let max = 2usize.pow(b as u32);
-    // find the candidate with the best weight
-    let best_candidate = (1..max)
+/// A prior distribution on the secret, for the solvers that take it into account
+#[derive(Debug, Clone, PartialEq)]
+pub enum SecretPrior {
+    /// Independent secret bits, given the probability that each bit is set
+    Bits(Vec<f64>),
+    /// The probability of each Hamming weight `0..=k`,
+    /// spread uniformly over the secrets of that weight
+    Weight(Vec<f64>),
+}
+
+impl SecretPrior {
+    /// The prior of a secret with independent bits of bias `delta_s`,
+    /// as obtained by [`sparse_secret_reduce`](crate::covering_codes::sparse_secret_reduce)
+    pub fn from_bias(k: usize, delta_s: f64) -> SecretPrior {
+        SecretPrior::Bits(vec![(1.0 - delta_s) / 2.0; k])
+    }
+
+    /// Get a function that computes the log prior probability of a candidate
+    /// up to an additive constant
+    fn log_prior(&self, k: usize) -> Box<dyn Fn(usize) -> f64 + Sync> {
+        match self {
+            SecretPrior::Bits(probabilities) => {
+                assert_eq!(probabilities.len(), k, "Need a probability for every bit");
+                let log_odds = probabilities
+                    .iter()
+                    .map(|&p| (p / (1.0 - p)).ln())
+                    .collect::<Vec<f64>>();
+                Box::new(move |candidate| {
+                    log_odds
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, _)| candidate >> i & 1 == 1)
+                        .map(|(_, log_odds)| log_odds)
+                        .sum()
+                })
+            }
+            SecretPrior::Weight(probabilities) => {
+                assert_eq!(
+                    probabilities.len(),
+                    k + 1,
+                    "Need a probability for every weight"
+                );
+                // log(P(w) / binomial(k, w))
+                let mut log_binomial = 0.0;
+                let per_weight = probabilities
+                    .iter()
+                    .enumerate()
+                    .map(|(w, &p)| {
+                        if w > 0 {
+                            log_binomial += ((k - w + 1) as f64 / w as f64).ln();
+                        }
+                        p.ln() - log_binomial
+                    })
+                    .collect::<Vec<f64>>();
+                Box::new(move |candidate| per_weight[candidate.count_ones() as usize])
+            }
+        }
+    }
+}
+
+/// Find the candidate with the highest posterior log-likelihood
+///
+/// A candidate with correlation `W` disagrees with `(n - W) / 2` of the samples,
+/// so up to a constant its log-likelihood is `W / 2 * ln((1 + delta) / (1 - delta))`.
+fn posterior_argmax(
+    k: usize,
+    delta: f64,
+    prior: &SecretPrior,
+    correlation: impl Fn(usize) -> i64 + Sync,
+) -> usize {
+    assert!(delta > 0.0 && delta < 1.0, "Need 0 < delta < 1");
+    let log_prior = prior.log_prior(k);
+    let scale = log_likelihood_weight(delta) / 2.0;
+    let posterior = |candidate: usize| scale * correlation(candidate) as f64 + log_prior(candidate);
+
+    let (best, score) = (0..2usize.pow(k as u32))
         .into_par_iter()
-        .max_by_key(|candidate| computation(*candidate))
+        .map(|candidate| (candidate, posterior(candidate)))
+        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
         .expect("Can't work on an empty list");
-
-    log::info!("Best candidate weight: {}", best_candidate.count_ones());
-    usize_to_binmatrix(best_candidate as u64, b).as_vector()
+    log::info!(
+        "Best candidate weight: {}, log posterior: {:.2}, of which log prior: {:.2}",
+        best.count_ones(),
+        score,
+        log_prior(best)
+    );
+    best
 }
 
 /// This is the LF2 reduction. This reduction grows the number of samples.
@@ -94,7 +202,10 @@ pub fn xor_reduce(oracle: &mut LpnOracle, b: u32) {
     xor_drop_reduce(oracle, b, 0)
 }
 
-fn fill_delete_ranges(deletes: &mut Vec<&mut [Sample]>, extras: &mut Vec<Sample>) {
+fn fill_delete_ranges<const N: usize>(
+    deletes: &mut Vec<&mut [Sample<N>]>,
+    extras: &mut Vec<Sample<N>>,
+) {
     while deletes.len() > 0 && extras.len() > 0 {
         let fillable = unsafe { deletes.pop().unchecked_unwrap() };
         let fillable_len = fillable.len();
@@ -121,7 +232,50 @@ fn fill_delete_ranges(deletes: &mut Vec<&mut [Sample]>, extras: &mut Vec<Sample>
     }
 }
 
+/// Remove the sorted, disjoint `ranges` from `samples`, keeping the order of the others
+fn remove_ranges<const N: usize>(samples: &mut Vec<Sample<N>>, ranges: &[ops::Range<usize>]) {
+    let mut write = 0;
+    let mut read = 0;
+    for range in ranges
+        .iter()
+        .chain(std::iter::once(&(samples.len()..samples.len())))
+    {
+        debug_assert!(read <= range.start, "Ranges should be sorted and disjoint");
+        for idx in read..range.start {
+            samples.swap(write, idx);
+            write += 1;
+        }
+        read = range.end;
+    }
+    samples.truncate(write);
+}
+
+/// LF2 reduction that only keeps the samples that are zero on the `zero_bits` below the window
+///
+/// $k' = k - b$ (the zero bits are removed in a later reduction)
+/// $n' = n(n-1) / 2^{b+1+zero\_bits}$
+/// $\delta' = \delta^2$
 pub fn xor_drop_reduce(oracle: &mut LpnOracle, b: u32, zero_bits: usize) {
+    xor_drop_reduce_impl(oracle, b, zero_bits, None)
+}
+
+/// LF2 reduction like [`xor_drop_reduce`] that produces at most `target` samples
+///
+/// Every pair is kept with a probability such that we expect slightly more than `target`
+/// samples, and we stop generating pairs as soon as we have `target` samples.
+/// This bounds the memory use, also if some partitions are much larger than others.
+/// If the target is reached, the partitions that are processed last contribute fewer pairs,
+/// so the result is not a uniform sample of all pairs.
+///
+/// $k' = k - b$ (the zero bits are removed in a later reduction)
+/// $n' \leq target$
+/// $\delta' = \delta^2$
+pub fn xor_drop_reduce_bounded(oracle: &mut LpnOracle, b: u32, zero_bits: usize, target: usize) {
+    assert!(target > 0, "Need to produce at least one sample");
+    xor_drop_reduce_impl(oracle, b, zero_bits, Some(target))
+}
+
+fn xor_drop_reduce_impl(oracle: &mut LpnOracle, b: u32, zero_bits: usize, target: Option<usize>) {
     let k = oracle.get_k();
     let b = b as usize;
     assert!(b < k);
@@ -139,15 +293,73 @@ pub fn xor_drop_reduce(oracle: &mut LpnOracle, b: u32, zero_bits: usize) {
         expected_samples,
         log_2(expected_samples)
     );
+
+    // aim a bit higher than the target, we stop once we have enough
+    let keep_probability = match target {
+        Some(target) => (1.05 * target as f64 / expected_samples.max(1) as f64).min(1.0),
+        None => 1.0,
+    };
+    if keep_probability < 1.0 {
+        log::debug!(
+            "Keeping pairs with probability {:.5} to obtain at most {} samples",
+            keep_probability,
+            target.unwrap()
+        );
+    }
+    with_samples!(&mut oracle.samples, samples => {
+        xor_partitions(samples, k, b, zero_bits, target, keep_probability)
+    });
+    if let Some(target) = target {
+        debug_assert!(oracle.samples.len() <= target);
+        if oracle.samples.len() < target {
+            log::warn!(
+                "Only obtained {} of the {} requested samples",
+                oracle.samples.len(),
+                target
+            );
+        }
+    }
+
+    // Set the new k
+    log::trace!("truncating oracle");
+    unsafe { oracle.set_k(k - b) };
+    oracle.secret.truncate(k - b, true);
+    oracle.delta = oracle.delta.powi(2);
+    log::debug!(
+        "xor-reduce iteration done, {} samples (2^{}) now, k' = {}",
+        oracle.samples.len(),
+        log_2(oracle.samples.len()),
+        oracle.get_k()
+    );
+}
+
+/// Add the pairs of samples in every partition on the window of `b` bits below `k`
+///
+/// Replaces the samples by the sums that are zero on the `zero_bits` below the window,
+/// see [`xor_drop_reduce_bounded`] for `target` and `keep_probability`.
+fn xor_partitions<const N: usize>(
+    samples: &mut Vec<Sample<N>>,
+    k: usize,
+    b: usize,
+    zero_bits: usize,
+    target: Option<usize>,
+    keep_probability: f64,
+) {
+    let num_samples = samples.len();
+    let produced = AtomicUsize::new(0);
+    // reserve a place for a new sample, fails once we have reached the target
+    let reserve = || match target {
+        Some(target) => produced.fetch_add(1, Ordering::Relaxed) < target,
+        None => true,
+    };
     // Partition into V_j
     let bitrange: ops::Range<usize> = (k - b)..k;
-    oracle
-        .samples
-        .par_sort_unstable_by_key(|q| query_bits_range(q, bitrange.clone()));
+    log::debug!("Collecting Pivots");
+    let pivots = create_pivots(samples, &bitrange);
 
-    let dup_count = (&oracle.samples[1..])
+    let dup_count = (&samples[1..])
         .iter()
-        .fold((&oracle.samples[0], 0usize), |(prev, count), s| {
+        .fold((&samples[0], 0usize), |(prev, count), s| {
             if prev == s {
                 (s, count + 1)
             } else {
@@ -159,25 +371,28 @@ pub fn xor_drop_reduce(oracle: &mut LpnOracle, b: u32, zero_bits: usize) {
         log::warn!("There are {} duplicate samples", dup_count);
     }
 
-    log::debug!("Collecting Pivots");
-    let pivots = create_pivots(&mut oracle.samples, &bitrange);
     log::debug!("Creating PartitionIterator");
-    let partitions = create_partitions(&mut oracle.samples, &pivots);
+    let oracle_start = samples.as_ptr() as usize;
+    let partitions = create_partitions(samples, &pivots);
 
     log::debug!("xor-reducing");
-    let (mut delete_ranges, mut extra_stuff): (Vec<_>, Vec<Sample>) = partitions
+    let (mut delete_ranges, mut extra_stuff): (Vec<_>, Vec<Sample<N>>) = partitions
         .into_par_iter()
         .fold(
             || {
                 (
-                    Vec::<&mut [Sample]>::new(), // Ranges that should be filled or emptied out
-                    Vec::<Sample>::new(),        // Additional samples
+                    Vec::<&mut [Sample<N>]>::new(), // Ranges that should be filled or emptied out
+                    Vec::<Sample<N>>::new(),        // Additional samples
                 )
             },
-            |(mut deletes, mut extras), partition: &mut [Sample]| {
+            |(mut deletes, mut extras), partition: &mut [Sample<N>]| {
+                let mut rng = lpn_thread_rng();
+                let done = target.is_some_and(|target| produced.load(Ordering::Relaxed) >= target);
                 let new_samples = partition
                     .iter()
                     .tuple_combinations()
+                    .take_while(|_| !done)
+                    .filter(|_| keep_probability >= 1.0 || rng.gen_bool(keep_probability))
                     .map(|(v1, v2)| {
                         debug_assert_eq!(
                             query_bits_range(v1, bitrange.clone()),
@@ -194,17 +409,9 @@ pub fn xor_drop_reduce(oracle: &mut LpnOracle, b: u32, zero_bits: usize) {
                         );
                         vnew
                     })
-                    .collect::<Vec<_>>();
-                extras.reserve_exact(new_samples.len());
-                if zero_bits != 0 {
-                    extras.extend(
-                        new_samples
-                            .into_iter()
-                            .filter(|x| are_last_bits_zero(x, k, zero_bits)),
-                    );
-                } else {
-                    extras.extend(new_samples);
-                };
+                    .filter(|x| zero_bits == 0 || are_last_bits_zero(x, k - b, zero_bits))
+                    .take_while(|_| reserve());
+                extras.extend(new_samples);
 
                 if deletes.capacity() < 10 {
                     deletes.reserve_exact(1000);
@@ -218,8 +425,8 @@ pub fn xor_drop_reduce(oracle: &mut LpnOracle, b: u32, zero_bits: usize) {
         )
         .reduce(
             || (Vec::new(), Vec::new()),
-            |(mut a_delete, mut a_extra): (Vec<&mut [Sample]>, Vec<Sample>),
-             (mut b_delete, mut b_extra): (Vec<_>, Vec<Sample>)| {
+            |(mut a_delete, mut a_extra): (Vec<&mut [Sample<N>]>, Vec<Sample<N>>),
+             (mut b_delete, mut b_extra): (Vec<_>, Vec<Sample<N>>)| {
                 fill_delete_ranges(&mut a_delete, &mut b_extra);
                 fill_delete_ranges(&mut b_delete, &mut a_extra);
 
@@ -259,46 +466,33 @@ pub fn xor_drop_reduce(oracle: &mut LpnOracle, b: u32, zero_bits: usize) {
         num_extra_samples
     );
 
-    // sort samples to clear out remainder
+    // compact the samples to clear out the remainder
     if delete_count > 0 {
-        delete_ranges.into_par_iter().for_each(|deletable_samples| {
-            for sample in deletable_samples {
-                for block in sample.get_sample_mut() {
-                    *block = !0;
-                }
-            }
-        });
-
-        oracle.samples.par_sort_unstable();
-        debug_assert_eq!(oracle.samples.last().unwrap().get_sample()[0], !0);
-
-        oracle.samples.truncate(oracle.samples.len() - delete_count);
+        let mut delete_ranges = delete_ranges
+            .into_iter()
+            .filter(|deletable_samples| !deletable_samples.is_empty())
+            .map(|deletable_samples| {
+                let start =
+                    (deletable_samples.as_ptr() as usize - oracle_start) / size_of::<Sample<N>>();
+                start..(start + deletable_samples.len())
+            })
+            .collect::<Vec<_>>();
+        delete_ranges.sort_unstable_by_key(|range| range.start);
+        remove_ranges(samples, &delete_ranges);
+        debug_assert_eq!(samples.len(), num_samples - delete_count);
     }
 
     log::trace!(
         "extending with {} newly generated samples",
         num_extra_samples
     );
-    oracle.samples.reserve_exact(num_extra_samples);
-    oracle.samples.extend(extra_stuff);
-    oracle.samples.shrink_to_fit();
+    samples.reserve_exact(num_extra_samples);
+    samples.extend(extra_stuff);
+    samples.shrink_to_fit();
 
-    debug_assert!(!oracle
-        .samples
+    debug_assert!(!samples
         .iter()
         .any(|q| query_bits_range(q, bitrange.clone()) != 0));
-
-    // Set the new k
-    log::trace!("truncating oracle");
-    unsafe { oracle.set_k(k - b) };
-    oracle.secret.truncate(k - b, true);
-    oracle.delta = oracle.delta.powi(2);
-    log::debug!(
-        "xor-reduce iteration done, {} samples (2^{}) now, k' = {}",
-        oracle.samples.len(),
-        log_2(oracle.samples.len()),
-        oracle.get_k()
-    );
 }
 
 /// Solving using the Fast Walsh-Hamadard Transform
@@ -311,14 +505,129 @@ pub fn fwht_solve(oracle: LpnOracle) -> BinVector {
 
     let k = oracle.get_k() as u32;
     let mut majority_counter = count_samples(oracle);
+    solve_counters(&mut majority_counter, k, parfwht, argmax)
+}
+
+/// Solving using the FWHT, counting the samples of a stream chunk by chunk
+///
+/// Never holds more than one chunk of samples next to the `2^k` counters.
+pub fn fwht_solve_streaming(stream: SampleStream) -> BinVector {
+    let k = stream.oracle().get_k() as u32;
+    log::info!(
+        "Streaming FWHT solving for k' = {}, {} samples (2^{})",
+        k,
+        stream.remaining(),
+        log_2(stream.remaining())
+    );
+    assert!((k as usize) < crate::util::num_bits::<usize>());
+
+    let mut sum_vector = Vec::new();
+    sum_vector.resize_with(2usize.pow(k), || AtomicI64::new(0));
+    for chunk in stream {
+        chunk.par_iter().for_each(|sample| {
+            let idx = sample.get_block(0) as usize;
+            sum_vector[idx].fetch_add(if sample.get_product() { -1 } else { 1 }, Ordering::Relaxed);
+        });
+    }
+    let mut majority_counter = sum_vector
+        .into_iter()
+        .map(AtomicI64::into_inner)
+        .collect::<Vec<_>>();
+    solve_counters(&mut majority_counter, k, parfwht, argmax)
+}
+
+/// Solving using the FWHT, ranking candidates by their posterior log-likelihood
+///
+/// Combines the Walsh spectrum with a prior on the secret,
+/// which matters if the bias of the samples is borderline.
+pub fn fwht_solve_with_prior(oracle: LpnOracle, prior: &SecretPrior) -> BinVector {
+    log::info!("FWHT solving with prior for k' = {}", oracle.get_k());
+    assert!(oracle.get_k() < crate::util::num_bits::<usize>());
+
+    let k = oracle.get_k() as u32;
+    let delta = oracle.delta;
+    let mut majority_counter = count_samples(oracle);
+    solve_counters(&mut majority_counter, k, parfwht, |spectrum| {
+        posterior_argmax(k as usize, delta, prior, |candidate| spectrum[candidate])
+    })
+}
+
+/// The log-likelihood ratio `ln((1 + delta) / (1 - delta))` of a sample with bias `delta`
+///
+/// Use this to weigh samples of different bias for [`fwht_solve_weighted`].
+pub fn log_likelihood_weight(delta: f64) -> f64 {
+    ((1.0 + delta) / (1.0 - delta)).ln()
+}
+
+/// Solving using the FWHT with a weight for every sample
+///
+/// Instead of adding `±1` for every sample, adds `±weights[i]` for sample `i`.
+/// With the log-likelihood ratios from [`log_likelihood_weight`] as weights,
+/// the best candidate maximises the likelihood even if the samples have different biases.
+pub fn fwht_solve_weighted(oracle: LpnOracle, weights: &[f64]) -> BinVector {
+    log::info!("Weighted FWHT solving for k' = {}", oracle.get_k());
+    assert!(oracle.get_k() < crate::util::num_bits::<usize>());
+    assert_eq!(
+        oracle.samples.len(),
+        weights.len(),
+        "Need a weight for every sample"
+    );
+    assert!(!oracle.samples.is_empty(), "What, no samples?");
 
+    let k = oracle.get_k() as u32;
+    let size = 2usize.pow(k);
+    // a single table of f64 scores, stored as bits to add to them atomically
+    let mut scores = Vec::new();
+    scores.resize_with(size, || AtomicU64::new(0f64.to_bits()));
+    with_samples!(&oracle.samples, samples => samples
+    .par_iter()
+    .zip(weights.par_iter())
+    .for_each(|(sample, &weight)| {
+        let idx = sample.get_block(0) as usize;
+        let weight = if sample.get_product() {
+            -weight
+        } else {
+            weight
+        };
+        let _ = scores[idx].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+            Some((f64::from_bits(bits) + weight).to_bits())
+        });
+    }));
+    let mut scores = scores
+        .into_iter()
+        .map(|bits| f64::from_bits(bits.into_inner()))
+        .collect::<Vec<f64>>();
+
+    solve_counters(&mut scores, k, parfwht_f64, argmax)
+}
+
+/// Transform the counters and return the candidate picked from the spectrum as the secret
+///
+/// This is the end shared by the FWHT solvers.
+pub(crate) fn solve_counters<T>(
+    counters: &mut [T],
+    k: u32,
+    transform: impl FnOnce(&mut [T], u32),
+    pick: impl FnOnce(&[T]) -> usize,
+) -> BinVector {
     log::debug!("FWHT");
-    parfwht(&mut majority_counter[..], k);
+    transform(counters, k);
+    candidate_to_binvector(pick(counters) as u64, k)
+}
 
-    let guess = (0..2usize.pow(k))
-        .max_by_key(|x| majority_counter[*x])
-        .unwrap();
+/// The candidate with the largest correlation in the spectrum
+pub(crate) fn argmax<T: PartialOrd + fmt::Debug + Sync>(spectrum: &[T]) -> usize {
+    let (guess, value) = spectrum
+        .par_iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+        .expect("Can't work on an empty spectrum");
+    log::debug!("Best candidate has correlation {:?}", value);
+    guess
+}
 
+/// The secret of `k` bits given by the candidate `guess`
+pub(crate) fn candidate_to_binvector(guess: u64, k: u32) -> BinVector {
     let mut result = BinVector::with_capacity(k as usize);
     for i in 0..k {
         result.push(guess >> i & 1 == 1);
@@ -326,20 +635,18 @@ pub fn fwht_solve(oracle: LpnOracle) -> BinVector {
     result
 }
 
-#[cfg(target_arch = "x86_64")]
-fn count_samples(oracle: LpnOracle) -> Vec<i64> {
+pub(crate) fn count_samples(oracle: LpnOracle) -> Vec<i64> {
     let k = oracle.get_k() as u32;
 
     let mut sum_vector = Vec::new();
     sum_vector.resize_with(2usize.pow(k), || AtomicI64::new(0));
 
-    oracle
-        .samples
-        .into_par_iter()
-        .for_each_with(&sum_vector[..], |counters, sample| {
-            let idx = sample.get_block(0) as usize;
-            counters[idx].fetch_add(if sample.get_product() { -1 } else { 1 }, Ordering::Relaxed);
-        });
+    with_samples!(oracle.samples, samples => samples
+    .into_par_iter()
+    .for_each_with(&sum_vector[..], |counters, sample| {
+        let idx = sample.get_block(0) as usize;
+        counters[idx].fetch_add(if sample.get_product() { -1 } else { 1 }, Ordering::Relaxed);
+    }));
     sum_vector
         .into_iter()
         .map(|i| i.into_inner())
@@ -388,12 +695,7 @@ pub fn parfwht(data: &mut [i64], bits: u32) {
         if stride >= 4 {
             data.par_chunks_mut(2 * stride).for_each(|data| {
                 let (left, right) = data.split_at_mut(stride);
-                (0..stride).step_by(4).into_iter().for_each(|j| unsafe {
-                    let l = i64x4::from_slice_unaligned_unchecked(&left[j..j + 4]);
-                    let r = i64x4::from_slice_unaligned_unchecked(&right[j..j + 4]);
-                    (l + r).write_to_slice_unaligned_unchecked(&mut left[j..j + 4]);
-                    (l - r).write_to_slice_unaligned_unchecked(&mut right[j..j + 4]);
-                });
+                kernels::butterfly(left, right);
             });
         } else {
             data.par_chunks_mut(2 * stride).for_each(|data| {
@@ -410,10 +712,28 @@ pub fn parfwht(data: &mut [i64], bits: u32) {
     }
 }
 
+/// Fast Walsh Hamadard Transform on real-valued data, see [`parfwht`]
+pub fn parfwht_f64(data: &mut [f64], bits: u32) {
+    let n = 1 << bits;
+    assert!(data.len() == n);
+
+    let mut stride = n / 2;
+    while stride >= 1 {
+        data.par_chunks_mut(2 * stride).for_each(|data| {
+            let (left, right) = data.split_at_mut(stride);
+            left.iter_mut().zip(right.iter_mut()).for_each(|(l, r)| {
+                let (a, b) = (*l, *r);
+                *l = a + b;
+                *r = a - b;
+            });
+        });
+        stride >>= 1;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use rand::prelude::*;
 
     fn usize_to_binvec(c: u64, size: usize) -> BinVector {
         let bytes = c.to_be_bytes();
@@ -476,4 +796,83 @@ mod tests {
 
         assert_eq!(majority_1, majority_2, "Should be the same");
     }
+
+    #[test]
+    fn xor_drop_zero_bits() {
+        let mut oracle = LpnOracle::new(32, 1.0 / 8.0);
+        oracle.get_samples(4000);
+
+        // we expect about 3900 samples
+        xor_drop_reduce(&mut oracle, 8, 3);
+        assert_eq!(oracle.get_k(), 24);
+        assert!((3500..4300).contains(&oracle.samples.len()));
+        // the zero bits are the ones right below the window, not the last bits of the window
+        assert!(oracle
+            .samples
+            .to_vec()
+            .iter()
+            .all(|q| are_last_bits_zero(q, 24, 3)));
+    }
+
+    #[test]
+    fn bounded_xor_drop() {
+        let mut oracle = LpnOracle::new(32, 1.0 / 8.0);
+        oracle.get_samples(4000);
+        let secret = oracle.secret.as_binvector(22);
+
+        // we expect about 7800 samples
+        xor_drop_reduce_bounded(&mut oracle, 8, 2, 3000);
+        assert_eq!(oracle.get_k(), 24);
+        assert_eq!(oracle.samples.len(), 3000);
+        assert!(oracle
+            .samples
+            .to_vec()
+            .iter()
+            .all(|q| are_last_bits_zero(q, 24, 2)));
+        oracle.truncate(22);
+        assert_eq!(fwht_solve(oracle), secret);
+    }
+
+    #[test]
+    fn prior_fwht() {
+        let mut secret = BinVector::from_elem(16, false);
+        secret.set(4, true);
+        let secret = Sample::from_binvector(&secret, false);
+        let mut oracle = LpnOracle::new_with_secret(secret, 16, 1.0 / 4.0);
+        oracle.get_samples(2000);
+        let secret = oracle.secret.as_binvector(16);
+
+        let prior = SecretPrior::from_bias(16, 0.75);
+        assert_eq!(fwht_solve_with_prior(oracle.clone(), &prior), secret);
+        let prior = SecretPrior::Weight((0..=16).map(|w| 0.5f64.powi(w + 1)).collect());
+        assert_eq!(lf1_solve_with_prior(oracle, &prior), secret);
+    }
+
+    #[test]
+    fn log_prior() {
+        let bits = SecretPrior::Bits(vec![0.25, 0.5, 0.75]).log_prior(3);
+        assert!(bits(0b000).abs() < 1e-10);
+        assert!((bits(0b001) + 3f64.ln()).abs() < 1e-10);
+        assert!((bits(0b101)).abs() < 1e-10);
+
+        let weights = SecretPrior::Weight(vec![0.1, 0.3, 0.3, 0.3]).log_prior(3);
+        assert!((weights(0b010) - 0.1f64.ln()).abs() < 1e-10);
+        assert!((weights(0b011) - 0.1f64.ln()).abs() < 1e-10);
+        assert!((weights(0b111) - 0.3f64.ln()).abs() < 1e-10);
+    }
+
+    #[test]
+    fn weighted_fwht() {
+        let mut oracle = LpnOracle::new(16, 1.0 / 8.0);
+        oracle.get_samples(2000);
+        let secret = oracle.secret.as_binvector(16);
+        let weights = vec![log_likelihood_weight(oracle.delta); 2000];
+        assert_eq!(fwht_solve_weighted(oracle, &weights), secret);
+
+        let mut data = (0..1024).map(|i| (i % 7) as i64 - 3).collect::<Vec<_>>();
+        let mut data_f64 = data.iter().map(|&x| x as f64).collect::<Vec<_>>();
+        parfwht(&mut data, 10);
+        parfwht_f64(&mut data_f64, 10);
+        assert!(data.iter().zip(data_f64).all(|(&a, b)| a as f64 == b));
+    }
 }
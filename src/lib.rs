@@ -1,4 +1,3 @@
-#![feature(maybe_uninit_slice)]
 //! This library provides everything you need to program attacks on LPN
 //! as if you were writing them on paper.
 #[cfg(feature = "jemallocator")]
@@ -20,12 +19,27 @@ extern crate rayon;
 #[cfg_attr(feature = "codes", macro_use)]
 extern crate serde;
 
+pub mod amplify;
+pub mod analytics;
 pub mod bkw;
 #[cfg(feature = "codes")]
+pub mod coded_bkw;
+pub mod coordinates;
+#[cfg(feature = "codes")]
 pub mod covering_codes;
+pub mod csum;
+pub mod decision;
+pub mod fwht;
 pub mod gauss;
+pub mod guess;
+pub mod isd;
+pub mod kernels;
 pub mod lf1;
+pub mod lw;
 pub mod oracle;
+pub mod sieve;
+pub mod stream;
+pub mod syndrome;
 
 #[cfg(feature = "codes")]
 pub mod codes;
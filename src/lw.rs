@@ -0,0 +1,244 @@
+//! The LW solver from Bogos and Vaudenay for sparse secrets
+//!
+//! Instead of scoring all `2^k` candidates with the FWHT, we enumerate the candidates
+//! by increasing Hamming weight and score every one against the samples directly.
+//! Within one weight, the candidates are enumerated in revolving-door order,
+//! so consecutive candidates differ in two positions and updating the products
+//! `<a, s> + c` of all samples costs two XORs of a column of the sample matrix.
+//! This works for any `k` as long as the secret has low weight.
+use crate::{
+    kernels,
+    oracle::{with_samples, LpnOracle},
+    util::log_2,
+};
+use m4ri_rust::friendly::BinVector;
+use rayon::prelude::*;
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Mutex,
+};
+
+/// Solving by enumerating the candidates of weight at most `max_weight`
+///
+/// Returns the candidate that agrees with the most samples.
+/// We stop as soon as a candidate agrees with so many samples that a wrong candidate is unlikely
+/// to do so. Scoring a candidate is aborted once it disagrees with more samples than the best so far.
+pub fn lw_solve(oracle: LpnOracle, max_weight: usize) -> BinVector {
+    let k = oracle.get_k();
+    let n = oracle.samples.len();
+    assert!(n > 0, "What, no samples?");
+    assert!(max_weight <= k, "The weight can't be larger than k");
+    if oracle.delta_s <= 0.0 {
+        log::warn!("The secret is not sparse, low-weight candidates are not the most likely");
+    }
+
+    let candidates = (0..=max_weight).map(|w| binomial(k, w)).sum::<f64>();
+    log::info!(
+        "LW solving for k' = {} up to weight {}, {:.0} (2^{:.1}) candidates against {} samples (2^{})",
+        k,
+        max_weight,
+        candidates,
+        candidates.log2(),
+        n,
+        log_2(n)
+    );
+
+    // store the sample matrix by columns, as bitsets over the samples
+    let words = n.div_ceil(64);
+    let mut columns = vec![vec![0u64; words]; k];
+    let mut products = vec![0u64; words];
+    with_samples!(&oracle.samples, samples => {
+        for (i, sample) in samples.iter().enumerate() {
+            for (j, column) in columns.iter_mut().enumerate() {
+                column[i / 64] |= (sample.get_bit(j) as u64) << (i % 64);
+            }
+            products[i / 64] |= (sample.get_product() as u64) << (i % 64);
+        }
+    });
+
+    // a wrong candidate exceeds this with probability at most about candidates * exp(-noise^2 / 2n),
+    // the correct one has correlation around n * delta.
+    let noise = (2.0 * n as f64 * candidates.ln().max(1.0)).sqrt();
+    let expected = n as f64 * oracle.delta;
+    let threshold = if expected > noise {
+        // in number of disagreeing samples
+        ((n as f64 - (noise + expected) / 2.0) / 2.0) as usize
+    } else {
+        0
+    };
+
+    let search = Search {
+        best_errors: AtomicUsize::new(usize::MAX),
+        best: Mutex::new(Vec::new()),
+        found: AtomicBool::new(false),
+        threshold,
+    };
+    search.score(&products, &[]);
+
+    for weight in 1..=max_weight {
+        if search.found.load(Ordering::Relaxed) {
+            break;
+        }
+        log::debug!("Enumerating candidates of weight {}", weight);
+        // split by the largest position, enumerate the others in revolving-door order
+        ((weight - 1)..k).into_par_iter().for_each(|last| {
+            let mut state = products.clone();
+            kernels::xor_into(&mut state, &columns[last]);
+            let mut current = Vec::with_capacity(weight);
+            let mut support = Vec::with_capacity(weight);
+            let mut in_support = vec![false; last];
+            revolving_door(last, weight - 1, false, &mut current, &mut |combination| {
+                if search.found.load(Ordering::Relaxed) {
+                    return true;
+                }
+                // consecutive combinations only differ in a few positions
+                for &pos in &support {
+                    if !combination.contains(&pos) {
+                        in_support[pos] = false;
+                        kernels::xor_into(&mut state, &columns[pos]);
+                    }
+                }
+                for &pos in combination {
+                    if !in_support[pos] {
+                        in_support[pos] = true;
+                        kernels::xor_into(&mut state, &columns[pos]);
+                    }
+                }
+                support.clear();
+                support.extend_from_slice(combination);
+                support.push(last);
+                search.score(&state, &support);
+                support.pop();
+                false
+            });
+        });
+    }
+
+    let errors = search.best_errors.into_inner();
+    let support = search.best.into_inner().unwrap();
+    log::info!(
+        "Best candidate has weight {} and correlation {}",
+        support.len(),
+        n as i64 - 2 * errors as i64
+    );
+    let mut result = BinVector::from_elem(k, false);
+    for pos in support {
+        result.set(pos, true);
+    }
+    result
+}
+
+/// Shared state of the parallel enumeration
+struct Search {
+    best_errors: AtomicUsize,
+    best: Mutex<Vec<usize>>,
+    found: AtomicBool,
+    threshold: usize,
+}
+
+impl Search {
+    /// Count the samples that disagree with the candidate, from the products `<a, s> + c`
+    fn score(&self, state: &[u64], support: &[usize]) {
+        let mut errors = 0;
+        for chunk in state.chunks(64) {
+            errors += kernels::count_ones(chunk) as usize;
+            if errors >= self.best_errors.load(Ordering::Relaxed) {
+                return;
+            }
+        }
+        let mut best = self.best.lock().unwrap();
+        // check again, another thread may have found a better one in the meantime
+        if errors < self.best_errors.load(Ordering::Relaxed) {
+            log::trace!("New best candidate {:?} with {} errors", support, errors);
+            self.best_errors.store(errors, Ordering::Relaxed);
+            best.clear();
+            best.extend_from_slice(support);
+            if errors < self.threshold {
+                log::debug!("Candidate {:?} exceeds the threshold", support);
+                self.found.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Enumerate the `t`-subsets of `0..n` in revolving-door order
+///
+/// Consecutive subsets differ by replacing one element.
+/// Calls `visit` on every subset and stops when it returns `true`, which is then returned.
+fn revolving_door<F>(
+    n: usize,
+    t: usize,
+    reverse: bool,
+    chosen: &mut Vec<usize>,
+    visit: &mut F,
+) -> bool
+where
+    F: FnMut(&[usize]) -> bool,
+{
+    // R(n, t) = R(n-1, t), reverse(R(n-1, t-1)) + {n-1}
+    if t == 0 {
+        visit(chosen)
+    } else if t == n {
+        let len = chosen.len();
+        chosen.extend(0..n);
+        let stop = visit(chosen);
+        chosen.truncate(len);
+        stop
+    } else if !reverse {
+        if revolving_door(n - 1, t, false, chosen, visit) {
+            return true;
+        }
+        chosen.push(n - 1);
+        let stop = revolving_door(n - 1, t - 1, true, chosen, visit);
+        chosen.pop();
+        stop
+    } else {
+        chosen.push(n - 1);
+        let stop = revolving_door(n - 1, t - 1, false, chosen, visit);
+        chosen.pop();
+        stop || revolving_door(n - 1, t, true, chosen, visit)
+    }
+}
+
+fn binomial(n: usize, k: usize) -> f64 {
+    (0..k).map(|i| (n - i) as f64 / (i + 1) as f64).product()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::oracle::Sample;
+
+    #[test]
+    fn revolving_door_order() {
+        let mut subsets = Vec::new();
+        revolving_door(6, 3, false, &mut Vec::new(), &mut |subset| {
+            let mut subset = subset.to_vec();
+            subset.sort_unstable();
+            subsets.push(subset);
+            false
+        });
+        assert_eq!(subsets.len(), 20);
+        for pair in subsets.windows(2) {
+            let common = pair[0].iter().filter(|x| pair[1].contains(x)).count();
+            assert_eq!(common, 2, "{:?} and {:?}", pair[0], pair[1]);
+        }
+        subsets.sort();
+        subsets.dedup();
+        assert_eq!(subsets.len(), 20);
+    }
+
+    #[test]
+    fn run_lw() {
+        let mut secret = BinVector::from_elem(100, false);
+        secret.set(4, true);
+        secret.set(57, true);
+        secret.set(93, true);
+        let secret = Sample::from_binvector(&secret, false);
+        let mut oracle = LpnOracle::new_with_secret(secret, 100, 1.0 / 8.0);
+        oracle.delta_s = 0.9;
+        oracle.get_samples(2000);
+        let secret = oracle.secret.as_binvector(100);
+        assert_eq!(lw_solve(oracle, 4), secret);
+    }
+}
@@ -13,6 +13,7 @@ use std::{
 use rand::prelude::*;
 use rayon::prelude::*;
 
+use crate::kernels;
 use crate::random::lpn_thread_rng;
 use crate::util::log_2;
 
@@ -66,18 +67,24 @@ pub(crate) const NOISE_BIT_IDX: usize = bits_per_block() - 1;
 /// Mask to & with to extract just the noise bit
 pub(crate) const NOISE_BIT_MASK: StorageBlock = (1 as StorageBlock) << NOISE_BIT_IDX;
 
+/// The number of samples of which we compute the products at once
+const PRODUCT_BATCH_SIZE: usize = 1 << 12;
+
 pub(crate) type SampleStorage = [StorageBlock; SAMPLE_LEN];
 
 /// Represents a sample in the oracle
 ///
 /// `<a, s> + e = c`
+///
+/// The sample is stored in `N` blocks, with the noise bit as the highest bit of the last block.
+/// The oracle stores its samples in as few blocks as `k` allows, see [`Samples`].
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
 #[repr(transparent)]
-pub struct Sample {
-    sample: [StorageBlock; SAMPLE_LEN],
+pub struct Sample<const N: usize = SAMPLE_LEN> {
+    sample: [StorageBlock; N],
 }
 
-impl fmt::Debug for Sample {
+impl<const N: usize> fmt::Debug for Sample<N> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let sample = self
             .sample
@@ -89,53 +96,88 @@ impl fmt::Debug for Sample {
     }
 }
 
-impl Sample {
-    const fn new() -> Sample {
-        Sample {
-            sample: [0; SAMPLE_LEN],
-        }
+impl<const N: usize> Sample<N> {
+    /// The largest `k` that fits in this layout, next to the noise bit
+    pub const MAX_K: usize = N * bits_per_block() - 1;
+    /// Block in which noise bit is stored
+    const NOISE_BIT_BLOCK: usize = N - 1;
+
+    pub(crate) const fn new() -> Sample<N> {
+        Sample { sample: [0; N] }
     }
 
-    pub fn vector_product(&self, other: &Sample, len: usize) -> bool {
-        self.sample
-            .iter()
-            .zip(other.sample[0..=block_offset(len)].iter())
-            .fold(0, |acc, (v1, v2)| (v1 & v2).count_ones() + acc)
-            % 2
-            == 1
+    /// The inner product of the first `len` bits with another sample, of any layout
+    pub fn vector_product<const M: usize>(&self, other: &Sample<M>, len: usize) -> bool {
+        let blocks = block_offset(len) + 1;
+        kernels::and_parity(&self.sample[..blocks], &other.sample[..blocks])
     }
 
     /// Get the Hamming weight of the sample
     pub fn count_ones(&self) -> u32 {
-        let mut acc = 0;
-        for block in &self.sample[..SAMPLE_LEN - 1] {
-            acc += block.count_ones();
+        let last_block = self.get_block(Self::NOISE_BIT_BLOCK);
+        kernels::count_ones(&self.sample[..N - 1]) as u32 + last_block.count_ones()
+    }
+
+    /// Get the bit at index `idx` of `a`
+    pub fn get_bit(&self, idx: usize) -> bool {
+        debug_assert!(idx < Self::MAX_K, "Index {} is out of range", idx);
+        (self.sample[block_offset(idx)] >> (idx % bits_per_block())) & 1 == 1
+    }
+
+    /// Set the bit at index `idx` of `a`
+    pub fn set_bit(&mut self, idx: usize, value: bool) {
+        debug_assert!(idx < Self::MAX_K, "Index {} is out of range", idx);
+        let mask = ONE << (idx % bits_per_block());
+        if value {
+            self.sample[block_offset(idx)] |= mask;
+        } else {
+            self.sample[block_offset(idx)] &= !mask;
         }
-        let last_block = self.get_block(NOISE_BIT_BLOCK);
-        acc += last_block.count_ones();
-        acc
+    }
+
+    /// Remove the bits at the sorted `positions`, shifting the higher bits down
+    ///
+    /// Preserves the product.
+    pub fn remove_bits(&mut self, positions: &[usize]) {
+        debug_assert!(
+            positions.windows(2).all(|w| w[0] < w[1]),
+            "Positions not sorted"
+        );
+        let product = self.get_product();
+        self.set_product(false);
+        for &pos in positions.iter().rev() {
+            debug_assert!(pos < Self::MAX_K, "Index {} is out of range", pos);
+            let off = block_offset(pos);
+            let low_mask = (ONE << (pos % bits_per_block())) - 1;
+            let block = self.sample[off];
+            let mut shifted = (block & low_mask) | ((block >> 1) & !low_mask);
+            for idx in (off + 1)..N {
+                shifted |= (self.sample[idx] & 1) << (bits_per_block() - 1);
+                self.sample[idx - 1] = shifted;
+                shifted = self.sample[idx] >> 1;
+            }
+            self.sample[N - 1] = shifted;
+        }
+        self.set_product(product);
     }
 
     /// get the noisy inner product
     pub fn get_product(&self) -> bool {
-        (self.sample[NOISE_BIT_BLOCK] >> NOISE_BIT_IDX) == 1
+        (self.sample[Self::NOISE_BIT_BLOCK] >> NOISE_BIT_IDX) == 1
     }
 
     /// absorb another sample
-    pub fn xor_into(&mut self, other: &Sample) {
+    pub fn xor_into(&mut self, other: &Sample<N>) {
         let before_a = self.get_product();
         let before_b = other.get_product();
-        self.sample
-            .iter_mut()
-            .zip(other.sample.iter())
-            .for_each(|(v1, v2)| *v1 ^= v2);
+        kernels::xor_into(&mut self.sample, &other.sample);
         debug_assert_eq!(self.get_product(), before_a ^ before_b);
     }
 
     /// set noise bit
     pub fn set_product(&mut self, new_product: bool) {
-        self.sample[NOISE_BIT_BLOCK] &= !NOISE_BIT_MASK; // get without noise bit
-        self.sample[NOISE_BIT_BLOCK] |= if new_product { ONE << NOISE_BIT_IDX } else { 0 };
+        self.sample[Self::NOISE_BIT_BLOCK] &= !NOISE_BIT_MASK; // get without noise bit
+        self.sample[Self::NOISE_BIT_BLOCK] |= if new_product { ONE << NOISE_BIT_IDX } else { 0 };
     }
 
     /// Obtain the sample
@@ -149,7 +191,7 @@ impl Sample {
     }
 
     /// Obtain
-    pub fn into_inner(self) -> SampleStorage {
+    pub fn into_inner(self) -> [StorageBlock; N] {
         self.sample
     }
 
@@ -160,7 +202,7 @@ impl Sample {
         if used_bits > 0 {
             let off = block_offset(len);
             let msk = ((1 << used_bits) - 1)
-                | if off == NOISE_BIT_BLOCK && !truncating_secret {
+                | if off == Self::NOISE_BIT_BLOCK && !truncating_secret {
                     1 << NOISE_BIT_IDX
                 } else {
                     0
@@ -171,16 +213,33 @@ impl Sample {
                 self.sample[off] = new_v;
             }
         }
-        // zero out any other blocks
-        ((block_offset(len) + 1)..SAMPLE_LEN).for_each(|idx| {
-            if idx == NOISE_BIT_BLOCK && !truncating_secret {
-                self.sample[NOISE_BIT_BLOCK] &= NOISE_BIT_MASK;
+        // zero out any other blocks, including block_offset(len) if no bits of it are used
+        (blocks_required(len)..N).for_each(|idx| {
+            if idx == Self::NOISE_BIT_BLOCK && !truncating_secret {
+                self.sample[Self::NOISE_BIT_BLOCK] &= NOISE_BIT_MASK;
             } else {
                 self.sample[idx] = 0
             }
         });
     }
 
+    /// The same sample in a layout of `M` blocks
+    ///
+    /// The bits that do not fit in the new layout should be zero.
+    pub fn resize<const M: usize>(&self) -> Sample<M> {
+        let blocks = cmp::min(N, M);
+        let mut sample = Sample::<M>::new();
+        (0..blocks).for_each(|idx| sample.sample[idx] = self.get_block(idx));
+        debug_assert!(
+            (blocks..N).all(|idx| self.get_block(idx) == 0)
+                && sample.sample[M - 1] & NOISE_BIT_MASK == 0,
+            "Sample does not fit in {} blocks",
+            M
+        );
+        sample.set_product(self.get_product());
+        sample
+    }
+
     pub fn as_binvector(&self, len: usize) -> BinVector {
         let mut vec = BinVector::from_elem(len, false);
         let vecstorage = unsafe { vec.get_storage_mut() };
@@ -194,8 +253,8 @@ impl Sample {
         vec
     }
 
-    pub fn from_binvector(vec: &BinVector, product: bool) -> Sample {
-        debug_assert!(vec.len() < MAX_K);
+    pub fn from_binvector(vec: &BinVector, product: bool) -> Sample<N> {
+        debug_assert!(vec.len() < Self::MAX_K);
         let mut sample = Self::new();
         sample.sample[..blocks_required(vec.len())].copy_from_slice(unsafe {
             std::mem::transmute(&vec.get_storage()[..blocks_required(vec.len())])
@@ -215,8 +274,18 @@ impl Sample {
         self.set_product(product);
     }
 
+    /// Apply `f` to this sample in the layout of `MAX_K`
+    ///
+    /// For code that only works on that layout, like [`BinaryCode`](crate::codes::BinaryCode).
+    pub(crate) fn with_wide<R>(&mut self, f: impl FnOnce(&mut Sample) -> R) -> R {
+        let mut wide = self.resize();
+        let result = f(&mut wide);
+        *self = wide.resize();
+        result
+    }
+
     pub fn get_block(&self, index: usize) -> StorageBlock {
-        if index == NOISE_BIT_BLOCK {
+        if index == Self::NOISE_BIT_BLOCK {
             self.sample[index] & !NOISE_BIT_MASK
         } else {
             self.sample[index]
@@ -224,6 +293,139 @@ impl Sample {
     }
 }
 
+/// The samples of an oracle, in as few blocks per sample as `k` allows
+///
+/// The oracle repacks the samples whenever `k` crosses a block boundary.
+/// Use [`with_samples!`] to work on them in their current layout.
+#[derive(Clone)]
+pub enum Samples {
+    Blocks1(Vec<Sample<1>>),
+    Blocks2(Vec<Sample<2>>),
+    #[cfg(any(feature = "max_k_191", feature = "max_k_255"))]
+    Blocks3(Vec<Sample<3>>),
+    #[cfg(feature = "max_k_255")]
+    Blocks4(Vec<Sample<4>>),
+}
+
+/// Evaluate `$body` with `$name` bound to the vector of samples in their current layout
+///
+/// The body is compiled for every layout, so it can use the samples as a `Vec<Sample<N>>`.
+macro_rules! with_samples {
+    ($samples:expr, $name:ident => $body:expr) => {
+        match $samples {
+            $crate::oracle::Samples::Blocks1($name) => $body,
+            $crate::oracle::Samples::Blocks2($name) => $body,
+            #[cfg(any(feature = "max_k_191", feature = "max_k_255"))]
+            $crate::oracle::Samples::Blocks3($name) => $body,
+            #[cfg(feature = "max_k_255")]
+            $crate::oracle::Samples::Blocks4($name) => $body,
+        }
+    };
+}
+pub(crate) use with_samples;
+
+impl Samples {
+    /// No samples, in the layout for problems of size `k`
+    fn new(k: usize) -> Samples {
+        Samples::convert(Vec::<Sample<1>>::new(), blocks_required(k + 1))
+    }
+
+    /// The number of samples
+    pub fn len(&self) -> usize {
+        with_samples!(self, samples => samples.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of blocks per sample
+    pub fn blocks(&self) -> usize {
+        match self {
+            Samples::Blocks1(_) => 1,
+            Samples::Blocks2(_) => 2,
+            #[cfg(any(feature = "max_k_191", feature = "max_k_255"))]
+            Samples::Blocks3(_) => 3,
+            #[cfg(feature = "max_k_255")]
+            Samples::Blocks4(_) => 4,
+        }
+    }
+
+    /// Remove all samples
+    pub fn clear(&mut self) {
+        with_samples!(self, samples => samples.clear())
+    }
+
+    /// The samples in the layout of `MAX_K`
+    pub fn to_vec(&self) -> Vec<Sample> {
+        with_samples!(self, samples => samples.par_iter().map(Sample::resize).collect())
+    }
+
+    /// Replace the samples by `samples`, converted to the current layout
+    pub fn set<const N: usize>(&mut self, new_samples: Vec<Sample<N>>) {
+        with_samples!(self, samples => {
+            *samples = new_samples.par_iter().map(Sample::resize).collect()
+        })
+    }
+
+    /// Add `samples`, converted to the current layout
+    pub fn extend<const N: usize>(&mut self, new_samples: Vec<Sample<N>>) {
+        with_samples!(self, samples => {
+            samples.par_extend(new_samples.par_iter().map(Sample::resize))
+        })
+    }
+
+    /// Take the samples, leaving no samples in the same layout
+    pub fn take(&mut self) -> Samples {
+        let empty = Samples::convert(Vec::<Sample<1>>::new(), self.blocks());
+        mem::replace(self, empty)
+    }
+
+    /// Move the samples to a layout of `blocks` blocks
+    fn convert<const N: usize>(samples: Vec<Sample<N>>, blocks: usize) -> Samples {
+        fn resized<const N: usize, const M: usize>(samples: Vec<Sample<N>>) -> Vec<Sample<M>> {
+            samples.into_par_iter().map(|q| q.resize()).collect()
+        }
+        match blocks {
+            1 => Samples::Blocks1(resized(samples)),
+            2 => Samples::Blocks2(resized(samples)),
+            #[cfg(any(feature = "max_k_191", feature = "max_k_255"))]
+            3 => Samples::Blocks3(resized(samples)),
+            #[cfg(feature = "max_k_255")]
+            4 => Samples::Blocks4(resized(samples)),
+            _ => panic!("Can't store samples in {} blocks", blocks),
+        }
+    }
+
+    /// Repack the samples into `blocks` blocks, if that is not the current layout
+    fn repack(&mut self, blocks: usize) {
+        if blocks != self.blocks() {
+            log::debug!(
+                "Repacking {} samples from {} into {} blocks",
+                self.len(),
+                self.blocks(),
+                blocks
+            );
+            let samples = mem::replace(self, Samples::Blocks1(Vec::new()));
+            *self = with_samples!(samples, samples => Samples::convert(samples, blocks));
+        }
+    }
+}
+
+/// A reduction that removed coordinates of the secret at arbitrary positions
+///
+/// Recorded in the history of the oracle so we can reassemble the original secret.
+/// The positions are those before the reduction was applied.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reduction {
+    /// The drop reduction removed these coordinates, the secret bits are unknown
+    Drop(Vec<usize>),
+    /// The trunc reduction removed these coordinates, the secret bits are unknown
+    Trunc(Vec<usize>),
+    /// These coordinates were removed because we know the secret bits
+    KnownBits(Vec<(usize, bool)>),
+}
+
 /// This struct represents the oracle of the LPN problem.
 ///
 /// We need to obtain the queries needed before applying reductions or transformations.
@@ -232,9 +434,13 @@ pub struct LpnOracle {
     /// The samples held by this oracle.
     ///
     /// Can be obtained via `get_samples`
-    pub samples: Vec<Sample>,
+    pub samples: Samples,
     /// The secret of this problem
+    ///
+    /// Only meaningful if the secret is known, see [`LpnOracle::is_secret_known`].
     pub secret: Sample,
+    /// Whether `secret` is the actual secret of the problem
+    secret_known: bool,
     /// The size of this problem
     k: usize,
     /// The bias of this problem
@@ -245,6 +451,14 @@ pub struct LpnOracle {
     pub(crate) sparse_transform_matrix: Option<BinMatrix>,
     /// The vector used by the sparse secret reduction
     pub(crate) sparse_transform_vector: Option<BinVector>,
+    /// Whether this oracle hands out uniformly random samples instead of LPN samples
+    uniform: bool,
+    /// The maximum number of samples we may query, if limited
+    query_budget: Option<usize>,
+    /// The number of samples queried so far
+    queries: usize,
+    /// The reductions that removed coordinates, in the order they were applied
+    pub(crate) history: Vec<Reduction>,
 }
 
 impl LpnOracle {
@@ -256,9 +470,6 @@ impl LpnOracle {
             "We require K limit to be hardcoded, sorry. Max K for this build: {}",
             MAX_K
         );
-        if blocks_required(k + 1) < blocks_required(MAX_K + 1) {
-            log::warn!("WARNING: you could reduce MAX_K, be faster and use less memory!");
-        }
         debug_assert!((0.0..1.0).contains(&tau), "0 <= tau < 1");
         debug_assert!(k > 0, "should have k > 0");
         let mut secret = Sample {
@@ -268,16 +479,79 @@ impl LpnOracle {
         log::info!("Constructed Oracle with k={}, τ={:0.5}", k, tau);
 
         LpnOracle {
-            samples: vec![],
+            samples: Samples::new(k),
             secret,
+            secret_known: true,
             k,
             delta: 1f64 - 2f64 * tau,
             delta_s: 0f64, // uniformly random
             sparse_transform_matrix: None,
             sparse_transform_vector: None,
+            uniform: false,
+            query_budget: None,
+            queries: 0,
+            history: Vec::new(),
         }
     }
 
+    /// Create an oracle that hands out uniformly random samples
+    ///
+    /// The samples look like those of an LPN problem with the same `k` and `tau`,
+    /// but the products are independent of the secret.
+    /// Use this to calibrate distinguishers for decision-LPN.
+    pub fn new_uniform(k: u32, tau: f64) -> LpnOracle {
+        let mut lpn = Self::new(k, tau);
+        lpn.uniform = true;
+        lpn
+    }
+
+    /// Does this oracle hand out uniformly random samples?
+    pub fn is_uniform(&self) -> bool {
+        self.uniform
+    }
+
+    /// Do we know the secret of this problem?
+    ///
+    /// Only false for problems imported from elsewhere, in which case checks and logs
+    /// against `secret` are skipped.
+    pub fn is_secret_known(&self) -> bool {
+        self.secret_known
+    }
+
+    /// Mark the secret as unknown, and set it to zero
+    pub fn forget_secret(&mut self) {
+        self.secret = Sample::new();
+        self.secret_known = false;
+    }
+
+    /// The reductions that removed coordinates, in the order they were applied
+    pub fn history(&self) -> &[Reduction] {
+        &self.history
+    }
+
+    /// Limit the total number of samples that may be queried from this oracle
+    ///
+    /// Querying more samples than the budget allows panics.
+    pub fn set_query_budget(&mut self, budget: usize) {
+        self.query_budget = Some(budget);
+    }
+
+    /// The query budget, if any
+    pub fn query_budget(&self) -> Option<usize> {
+        self.query_budget
+    }
+
+    /// The number of samples queried from this oracle so far
+    pub fn queries(&self) -> usize {
+        self.queries
+    }
+
+    /// The number of samples we may still query, if the queries are limited
+    pub fn remaining_queries(&self) -> Option<usize> {
+        self.query_budget
+            .map(|budget| budget.saturating_sub(self.queries))
+    }
+
     /// Create a new LPN problem with a set secret
     pub fn new_with_secret(secret: Sample, k: u32, tau: f64) -> LpnOracle {
         let mut lpn = Self::new(k, tau);
@@ -291,93 +565,66 @@ impl LpnOracle {
     ///
     /// Uses parallelism
     pub fn get_samples(&mut self, n: usize) {
-        let mut input_samples = Vec::with_capacity(n);
-        self.get_some_samples(&mut input_samples, n);
-        self.samples.reserve_exact(input_samples.len());
-        self.samples.extend(input_samples);
+        self.count_queries(n);
+        let noise = self.noise();
+        let (k, secret) = (self.k, &self.secret);
+        with_samples!(&mut self.samples, samples => {
+            let mut input_samples = Vec::with_capacity(n);
+            generate_samples(&mut input_samples, n, k, secret, noise);
+            samples.reserve_exact(input_samples.len());
+            samples.extend(input_samples);
+        });
     }
 
-    fn get_some_samples(&self, result: &mut Vec<Sample>, n: usize) {
-        let k = self.k as usize;
-
-        let tau = (1.0 - self.delta) / 2.0;
-        let dist = Bernoulli::new(tau).unwrap();
-        let secret = &self.secret;
-
-        // allocate the space.
-        result.reserve_exact(n);
-        // obtain the vector itself as maybeuninit
-        let samples = unsafe {
-            // this is okay because MaybeUninit is #[repr(transparant)]
-            std::mem::transmute::<&mut Vec<Sample>, &mut Vec<MaybeUninit<Sample>>>(result)
-        };
-        // we cheat the size by using set_len
-        unsafe { samples.set_len(n) };
-        if result.capacity() > (n + 10000) {
-            result.shrink_to_fit();
+    /// Record `n` queries to the oracle, panics if this exceeds the budget
+    fn count_queries(&mut self, n: usize) {
+        self.queries += n;
+        if let Some(budget) = self.query_budget {
+            assert!(
+                self.queries <= budget,
+                "Query budget of {} samples exceeded, tried to query {} samples",
+                budget,
+                self.queries
+            );
         }
-        // bitbang some contents into you, multithreaded of course
-        let chunk_size: usize = std::cmp::max(n / rayon::current_num_threads(), 10_000);
-        samples
-            .par_chunks_mut(chunk_size)
-            .for_each_init(lpn_thread_rng, |rng, samples| {
-                let new_samples = MaybeUninit::slice_as_mut_ptr(samples) as *mut u8;
-                let size = std::mem::size_of::<[StorageBlock; SAMPLE_LEN]>();
-                let new_samples =
-                    unsafe { std::slice::from_raw_parts_mut(new_samples, size * samples.len()) };
-                rng.fill_bytes(new_samples);
-            });
-
-        // these have now been initialized
-        let samples = unsafe {
-            std::mem::transmute::<&mut Vec<MaybeUninit<Sample>>, &mut Vec<Sample>>(samples)
-        };
+    }
 
-        if block_offset(k) < NOISE_BIT_BLOCK {
-            samples
-                .par_iter_mut()
-                .for_each_init(lpn_thread_rng, |rng, sample| {
-                    let noise_bit = dist.sample(rng);
-                    sample.sample[(block_offset(k) + 1)..SAMPLE_LEN]
-                        .iter_mut()
-                        .for_each(|block| *block = 0);
-                    sample.sample[block_offset(k)] &= (ONE << (k % bits_per_block())) - 1;
-                    let product = sample.vector_product(&secret, k) ^ noise_bit;
-                    if product {
-                        sample.sample[NOISE_BIT_BLOCK] |= NOISE_BIT_MASK;
-                    }
-                });
+    /// The distribution of the noise of new samples
+    fn noise(&self) -> Bernoulli {
+        // uniform noise makes the product independent of the secret
+        let tau = if self.uniform {
+            0.5
         } else {
-            samples
-                .par_iter_mut()
-                .for_each_init(lpn_thread_rng, |rng, sample| {
-                    sample.sample[NOISE_BIT_BLOCK] &= (ONE << (k % bits_per_block())) - 1;
-                    let noise_bit = dist.sample(rng);
-                    let product = sample.vector_product(&secret, k) ^ noise_bit;
-                    if product {
-                        sample.sample[NOISE_BIT_BLOCK] |= ONE << NOISE_BIT_IDX;
-                    }
-                });
-        }
-        if cfg!(debug_assertions) {
-            let max_k = cmp::min(k + 10, MAX_K);
-            for sample in samples {
-                debug_assert_eq!(query_bits_range(sample, k..max_k), 0);
-            }
-        }
+            (1.0 - self.delta) / 2.0
+        };
+        Bernoulli::new(tau).unwrap()
     }
 
     /// Get samples from the oracle with a trailing number of zero bits
     ///
     /// Uses parallelism
     pub fn get_samples_drop(&mut self, n: usize, trailing_zeros: usize) {
-        let original_len = self.samples.len();
         log::trace!(
             "Getting additional samples until we have {} (2^{}) that have {} trailing zeros",
             n,
             log_2(n),
             trailing_zeros
         );
+        let mut samples = self.samples.take();
+        with_samples!(&mut samples, samples => self.get_samples_drop_into(samples, n, trailing_zeros));
+        self.samples = samples;
+        self.k -= trailing_zeros;
+        self.secret.truncate(self.k, true);
+        self.repack();
+    }
+
+    fn get_samples_drop_into<const N: usize>(
+        &mut self,
+        samples: &mut Vec<Sample<N>>,
+        n: usize,
+        trailing_zeros: usize,
+    ) {
+        let original_len = samples.len();
         let k = self.k;
 
         //let progress = ProgressBar::new(n as u64);
@@ -390,35 +637,96 @@ impl LpnOracle {
         }
 
         let mut input_vec = Vec::new();
-        const SAMPLE_SIZE: usize = mem::size_of::<Sample>();
-        while (self.samples.len() - original_len) < n {
+        let sample_size = mem::size_of::<Sample<N>>();
+        while (samples.len() - original_len) < n {
             // do some minimal amount of samples to reduce short iterations
-            let samples_to_get = n - (self.samples.len() - original_len);
+            let samples_to_get = n - (samples.len() - original_len);
 
             let samples_to_get = if let Ok(meminfo) = sys_info::mem_info() {
                 std::cmp::min(
                     samples_to_get << trailing_zeros,
                     // include the current capacity, otherwise we only use a third or so of RAM
-                    (5 * ((input_vec.capacity() * SAMPLE_SIZE) + ((meminfo.free * 1000) as usize)))
-                        / (6 * SAMPLE_SIZE),
+                    (5 * ((input_vec.capacity() * sample_size) + ((meminfo.free * 1000) as usize)))
+                        / (6 * sample_size),
                 )
             } else {
                 std::cmp::min(samples_to_get << trailing_zeros, 2usize.pow(28))
             };
-            // get_some_samples manages the size of input_vec.
-            self.get_some_samples(&mut input_vec, samples_to_get);
-            let before_extend = self.samples.len();
-            self.samples.par_extend(
+            // don't query more than we are allowed to, count_queries panics if we run out.
+            let samples_to_get = match self.remaining_queries() {
+                Some(remaining) => std::cmp::min(samples_to_get, std::cmp::max(remaining, 1)),
+                None => samples_to_get,
+            };
+            // generate_samples manages the size of input_vec.
+            self.count_queries(samples_to_get);
+            generate_samples(
+                &mut input_vec,
+                samples_to_get,
+                k,
+                &self.secret,
+                self.noise(),
+            );
+            let before_extend = samples.len();
+            samples.par_extend(
                 input_vec
                     .par_drain(..)
                     .filter(|sample| are_last_bits_zero(sample, k, trailing_zeros)),
             );
-            progress.inc((self.samples.len() - before_extend) as u64);
+            progress.inc((samples.len() - before_extend) as u64);
         }
         progress.finish_and_clear();
-        self.samples.truncate(n);
-        self.samples.shrink_to_fit();
+        samples.truncate(n);
+        samples.shrink_to_fit();
+    }
+
+    /// Get samples with a trailing number of zero bits, sampling them directly
+    ///
+    /// Gives the same result as [`LpnOracle::get_samples_drop`] without generating the
+    /// samples that would be dropped, which makes simulations much faster for larger
+    /// `trailing_zeros`. The number of queries is still counted as if we had dropped samples:
+    /// every sample costs a geometrically distributed number of queries with mean `2^trailing_zeros`.
+    pub fn get_samples_drop_direct(&mut self, n: usize, trailing_zeros: usize) {
+        log::trace!(
+            "Directly sampling {} (2^{}) samples with {} trailing zeros",
+            n,
+            log_2(n),
+            trailing_zeros
+        );
+        assert!(trailing_zeros < self.k, "Can't zero all bits");
+        assert!(trailing_zeros < 64, "Too many trailing zeros");
+
+        // the number of queries until we find a sample with trailing zeros is geometric
+        let queries = if trailing_zeros == 0 {
+            n
+        } else {
+            let log_failure = (1.0 - 1.0 / (1u64 << trailing_zeros) as f64).ln();
+            (0..n)
+                .into_par_iter()
+                .map_init(lpn_thread_rng, |rng, _| {
+                    let uniform: f64 = 1.0 - rng.gen::<f64>();
+                    1 + (uniform.ln() / log_failure).floor() as usize
+                })
+                .sum::<usize>()
+        };
+        log::trace!(
+            "This would have taken {} (2^{}) queries",
+            queries,
+            log_2(queries)
+        );
+        self.count_queries(queries);
+
+        // samples with trailing zeros are uniform samples on the remaining bits
         self.k -= trailing_zeros;
+        self.secret.truncate(self.k, true);
+        self.repack();
+        let noise = self.noise();
+        let (k, secret) = (self.k, &self.secret);
+        with_samples!(&mut self.samples, samples => {
+            let mut input_vec = Vec::new();
+            generate_samples(&mut input_vec, n, k, secret, noise);
+            samples.reserve_exact(n);
+            samples.extend(input_vec);
+        });
     }
 
     pub fn get_k(&self) -> usize {
@@ -426,62 +734,147 @@ impl LpnOracle {
     }
 
     /// Override what the value of k is, without running truncate.
+    ///
+    /// The samples should not have any bits set from `new_k` on.
     pub unsafe fn set_k(&mut self, new_k: usize) {
-        self.k = new_k
+        self.k = new_k;
+        self.repack();
+    }
+
+    /// Store the samples in as few blocks as `k` allows
+    fn repack(&mut self) {
+        self.samples.repack(blocks_required(self.k + 1));
     }
 
     /// Updates the problem to have fewer bits
+    ///
+    /// Repacks the samples into fewer blocks if `new_k` allows.
     pub fn truncate(&mut self, new_k: usize) {
-        // update k
-        let traverses_blocks = block_offset(self.k) > block_offset(new_k);
+        let old_k = self.k;
         self.k = new_k;
+        with_samples!(&mut self.samples, samples => truncate_samples(samples, old_k, new_k));
+        self.secret.truncate(new_k, true);
+        self.repack();
+    }
+}
 
-        let used_bits = new_k % bits_per_block();
-        // If there are no unused bits, there's no need to perform masking.
-        if used_bits > 0 {
-            let off = block_offset(new_k);
-            let msk = ((1 << used_bits) - 1)
-                | if off == NOISE_BIT_BLOCK {
-                    1 << NOISE_BIT_IDX
-                } else {
-                    0
-                };
+/// Clear the bits from `new_k` on of samples of length `old_k`, keeping the noise bits
+fn truncate_samples<const N: usize>(samples: &mut [Sample<N>], old_k: usize, new_k: usize) {
+    let noise_bit_block = Sample::<N>::NOISE_BIT_BLOCK;
+    let traverses_blocks = blocks_required(new_k) <= block_offset(old_k);
+
+    let used_bits = new_k % bits_per_block();
+    // If there are no unused bits, there's no need to perform masking.
+    if used_bits > 0 {
+        let off = block_offset(new_k);
+        let msk = ((1 << used_bits) - 1)
+            | if off == noise_bit_block {
+                1 << NOISE_BIT_IDX
+            } else {
+                0
+            };
+
+        samples.par_iter_mut().for_each(|sample| {
+            let old_v = sample.sample[off];
+            let new_v = old_v & msk;
+            if new_v != old_v {
+                sample.sample[off] = new_v;
+            }
+        });
+    }
 
-            self.samples.par_iter_mut().for_each(|sample| {
-                let old_v = sample.sample[off];
-                let new_v = old_v & msk;
-                if new_v != old_v {
-                    sample.sample[off] = new_v;
+    if traverses_blocks {
+        // zero out any other blocks, including block_offset(new_k) if no bits of it are used
+        let start = blocks_required(new_k);
+        samples.par_iter_mut().for_each(|sample| {
+            (start..N).for_each(|idx| {
+                if idx == noise_bit_block {
+                    // only preserves the noise bit
+                    sample.sample[noise_bit_block] &= NOISE_BIT_MASK;
+                } else {
+                    sample.sample[idx] = 0
                 }
-            });
-        }
+            })
+        });
+    }
+}
 
-        if traverses_blocks {
-            // zero out any other blocks
-            let start = block_offset(new_k) + 1;
-            self.samples.par_iter_mut().for_each(|sample| {
-                (start..SAMPLE_LEN).for_each(|idx| {
-                    if idx == NOISE_BIT_BLOCK {
-                        // only preserves the noise bit
-                        sample.sample[NOISE_BIT_BLOCK] &= NOISE_BIT_MASK;
-                    } else {
-                        sample.sample[idx] = 0
-                    }
-                })
-            });
-        }
+/// Generate `n` samples of length `k` without counting them as queries
+///
+/// `result` should be empty.
+fn generate_samples<const N: usize>(
+    result: &mut Vec<Sample<N>>,
+    n: usize,
+    k: usize,
+    secret: &Sample,
+    dist: Bernoulli,
+) {
+    let secret = &secret.resize::<N>();
 
-        self.secret.truncate(new_k, true);
+    // allocate the space.
+    result.reserve_exact(n);
+    // obtain the vector itself as maybeuninit
+    let samples = unsafe {
+        // this is okay because MaybeUninit is #[repr(transparant)]
+        std::mem::transmute::<&mut Vec<Sample<N>>, &mut Vec<MaybeUninit<Sample<N>>>>(result)
+    };
+    // we cheat the size by using set_len
+    unsafe { samples.set_len(n) };
+    if result.capacity() > (n + 10000) {
+        result.shrink_to_fit();
+    }
+    // bitbang some contents into you, multithreaded of course
+    let chunk_size: usize = std::cmp::max(n / rayon::current_num_threads(), 10_000);
+    samples
+        .par_chunks_mut(chunk_size)
+        .for_each_init(lpn_thread_rng, |rng, samples| {
+            let new_samples = samples.as_mut_ptr() as *mut u8;
+            let size = std::mem::size_of::<[StorageBlock; N]>();
+            let new_samples =
+                unsafe { std::slice::from_raw_parts_mut(new_samples, size * samples.len()) };
+            rng.fill_bytes(new_samples);
+        });
+
+    // these have now been initialized
+    let samples = unsafe {
+        std::mem::transmute::<&mut Vec<MaybeUninit<Sample<N>>>, &mut Vec<Sample<N>>>(samples)
+    };
+
+    // cut the samples to k bits, which also clears the product bit,
+    // and compute the products in batches
+    samples.par_chunks_mut(PRODUCT_BATCH_SIZE).for_each_init(
+        || (lpn_thread_rng(), Vec::with_capacity(PRODUCT_BATCH_SIZE)),
+        |(rng, products), samples| {
+            for sample in samples.iter_mut() {
+                sample.sample[(block_offset(k) + 1)..N]
+                    .iter_mut()
+                    .for_each(|block| *block = 0);
+                sample.sample[block_offset(k)] &= (ONE << (k % bits_per_block())) - 1;
+            }
+            products.resize(samples.len(), false);
+            inner_products(samples, secret, products);
+            for (sample, &product) in samples.iter_mut().zip(products.iter()) {
+                if product ^ dist.sample(rng) {
+                    sample.sample[N - 1] |= NOISE_BIT_MASK;
+                }
+            }
+        },
+    );
+    if cfg!(debug_assertions) {
+        let max_k = cmp::min(k + 10, Sample::<N>::MAX_K);
+        for sample in samples {
+            debug_assert_eq!(query_bits_range(sample, k..max_k), 0);
+        }
     }
 }
 
 #[inline]
-pub fn are_last_bits_zero(b: &Sample, k: usize, n_bits: usize) -> bool {
+pub fn are_last_bits_zero<const N: usize>(b: &Sample<N>, k: usize, n_bits: usize) -> bool {
     n_bits == 0 || query_bits_range(b, k - n_bits..k) == 0
 }
 
 #[inline]
-pub(crate) fn query_bits_range(b: &Sample, range: Range<usize>) -> u64 {
+pub(crate) fn query_bits_range<const N: usize>(b: &Sample<N>, range: Range<usize>) -> u64 {
     debug_assert!(range.len() <= 64);
 
     let b1 = b.get_block(block_offset(range.start));
@@ -503,6 +896,23 @@ pub(crate) fn query_bits_range(b: &Sample, range: Range<usize>) -> u64 {
     b1 as u64
 }
 
+/// The inner products of the samples with `vector`, written to `out`
+///
+/// Includes the product of the samples if the product bit of `vector` is set.
+/// For a secret `s` with the product bit set, this gives `<a, s> + c`, the error of every sample.
+pub(crate) fn inner_products<const N: usize>(
+    samples: &[Sample<N>],
+    vector: &Sample<N>,
+    out: &mut [bool],
+) {
+    assert_eq!(samples.len(), out.len());
+    // this is okay because Sample is #[repr(transparent)]
+    let rows = unsafe {
+        std::slice::from_raw_parts(samples.as_ptr() as *const StorageBlock, samples.len() * N)
+    };
+    kernels::inner_products(rows, &vector.sample, out);
+}
+
 #[cfg(test)]
 mod test {
     use rand::prelude::*;
@@ -580,18 +990,166 @@ mod test {
         for _ in 0..10000 {
             let k = rng.gen_range(0..(MAX_K - 10));
             let vec = BinVector::random(k);
-            let mut sample = Sample::new();
+            let mut sample: Sample = Sample::new();
             sample.set_from_binvec(&vec);
             assert_eq!(vec, sample.as_binvector(k));
-            assert_eq!(vec, Sample::from_binvector(&vec, false).as_binvector(k));
-            assert_eq!(vec, Sample::from_binvector(&vec, true).as_binvector(k));
+            assert_eq!(vec, <Sample>::from_binvector(&vec, false).as_binvector(k));
+            assert_eq!(vec, <Sample>::from_binvector(&vec, true).as_binvector(k));
         }
     }
 
     #[test]
     fn test_from_binvec() {
         let binvec = BinVector::from_bytes(&[0b001000]);
-        let sample = Sample::from_binvector(&binvec, false);
+        let sample: Sample = Sample::from_binvector(&binvec, false);
         assert_eq!(sample.get_block(0) as usize, binvec.get_storage()[0]);
     }
+
+    #[test]
+    fn remove_bits() {
+        let rng = &mut rand::thread_rng();
+        for _ in 0..1000 {
+            let k = rng.gen_range(10..MAX_K);
+            let vec = BinVector::random(k);
+            let mut positions = rand::seq::index::sample(rng, k, 5).into_vec();
+            positions.sort_unstable();
+            let mut sample: Sample = Sample::from_binvector(&vec, true);
+            sample.remove_bits(&positions);
+
+            let expected = (0..k)
+                .filter(|i| !positions.contains(i))
+                .map(|i| vec.get(i).unwrap())
+                .chain(std::iter::repeat(false).take(5))
+                .collect::<Vec<_>>();
+            assert_eq!(sample.as_binvector(k), BinVector::from_bools(&expected));
+            assert!(sample.get_product());
+        }
+    }
+
+    #[test]
+    fn truncate_block_boundary() {
+        let mut sample = Sample {
+            sample: [StorageBlock::MAX; SAMPLE_LEN],
+        };
+        sample.truncate(64, false);
+        assert_eq!(sample.get_block(0), StorageBlock::MAX);
+        (1..SAMPLE_LEN).for_each(|idx| assert_eq!(sample.get_block(idx), 0));
+        assert!(sample.get_product());
+
+        let mut secret = Sample {
+            sample: [StorageBlock::MAX; SAMPLE_LEN],
+        };
+        secret.truncate(64, true);
+        assert!(!secret.get_product());
+
+        let mut oracle = LpnOracle::new(100, 1.0 / 8.0);
+        oracle.get_samples(100);
+        oracle.truncate(64);
+        let samples = oracle.samples.to_vec();
+        assert!(samples
+            .iter()
+            .all(|sample| (1..SAMPLE_LEN).all(|idx| sample.get_block(idx) == 0)));
+        assert!(samples.iter().any(Sample::get_product));
+        assert!((1..SAMPLE_LEN).all(|idx| oracle.secret.sample[idx] == 0));
+    }
+
+    #[test]
+    fn drop_direct() {
+        let mut oracle = LpnOracle::new(32, 1.0 / 8.0);
+        oracle.get_samples_drop_direct(10_000, 4);
+        assert_eq!(oracle.get_k(), 28);
+        assert_eq!(oracle.samples.len(), 10_000);
+        let samples = oracle.samples.to_vec();
+        assert!(samples
+            .iter()
+            .all(|sample| are_last_bits_zero(sample, 32, 4)));
+        // the number of queries has mean 160000 and standard deviation about 620
+        assert!((155_000..165_000).contains(&oracle.queries()));
+
+        let secret = oracle.secret.as_binvector(28);
+        let correct = samples
+            .iter()
+            .filter(|sample| {
+                let product = <Sample>::from_binvector(&secret, false).vector_product(sample, 28);
+                product == sample.get_product()
+            })
+            .count();
+        assert!((8500..9000).contains(&correct));
+    }
+
+    #[test]
+    fn drop_block_boundary() {
+        for &direct in &[false, true] {
+            let mut oracle = LpnOracle::new(70, 1.0 / 8.0);
+            if direct {
+                oracle.get_samples_drop_direct(1000, 8);
+            } else {
+                oracle.get_samples_drop(1000, 8);
+            }
+            assert_eq!(oracle.get_k(), 62);
+            assert_eq!(oracle.samples.blocks(), 1);
+            assert!(oracle.secret.sample[1..].iter().all(|&block| block == 0));
+
+            oracle.get_samples(10_000);
+            assert_eq!(oracle.samples.len(), 11_000);
+            let secret = oracle.secret.as_binvector(62);
+            let correct = oracle
+                .samples
+                .to_vec()
+                .iter()
+                .skip(1000)
+                .filter(|sample| {
+                    let product =
+                        <Sample>::from_binvector(&secret, false).vector_product(sample, 62);
+                    product == sample.get_product()
+                })
+                .count();
+            assert!((8500..9000).contains(&correct));
+        }
+    }
+
+    #[test]
+    fn repack() {
+        let mut oracle = LpnOracle::new(100, 1.0 / 8.0);
+        oracle.get_samples(10_000);
+        assert_eq!(oracle.samples.blocks(), 2);
+        let expected = oracle
+            .samples
+            .to_vec()
+            .into_iter()
+            .map(|mut sample| {
+                sample.truncate(60, false);
+                sample
+            })
+            .collect::<Vec<_>>();
+
+        oracle.truncate(60);
+        assert_eq!(oracle.samples.blocks(), 1);
+        assert_eq!(oracle.samples.to_vec(), expected);
+
+        // new samples are generated in the narrow layout
+        oracle.get_samples(10_000);
+        assert_eq!(oracle.samples.len(), 20_000);
+        let correct = oracle
+            .samples
+            .to_vec()
+            .iter()
+            .skip(10_000)
+            .filter(|sample| sample.vector_product(&oracle.secret, 60) == sample.get_product())
+            .count();
+        assert!((8500..9000).contains(&correct));
+
+        assert_eq!(LpnOracle::new(63, 1.0 / 8.0).samples.blocks(), 1);
+        assert_eq!(LpnOracle::new(64, 1.0 / 8.0).samples.blocks(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Query budget")]
+    fn query_budget() {
+        let mut oracle = LpnOracle::new(32, 1.0 / 8.0);
+        oracle.set_query_budget(1000);
+        oracle.get_samples(600);
+        assert_eq!(oracle.remaining_queries(), Some(400));
+        oracle.get_samples(600);
+    }
 }
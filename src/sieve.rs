@@ -0,0 +1,183 @@
+//! BKW with sieving: merge samples that are close on a window instead of equal
+//!
+//! Following the nearest-neighbour approach of May and Ozerov and Esser et al.,
+//! we search for pairs of samples whose sum has low weight on the window
+//! using locality-sensitive hashing: samples that agree on a random subset of the
+//! window positions end up in the same bucket, and close samples are much more likely
+//! to agree than random ones. The remaining low-weight window is absorbed into the noise.
+use crate::{
+    oracle::{query_bits_range, with_samples, LpnOracle},
+    random::lpn_thread_rng,
+    util::log_2,
+};
+use fnv::FnvHashSet;
+use rayon::prelude::*;
+use std::ops;
+
+/// Sieving reduction on the last `b` bits
+///
+/// Collects up to `list_size` sums of pairs of samples with weight at most `weight` on the window.
+/// The window is then dropped, which adds `<e, s>` to the noise for the remaining window `e`.
+///
+/// `$k' = k - b$`
+/// `$n' \leq list\_size$`
+/// `$d' = d^2 * E[d_s^{wt(e)}]$`
+pub fn sieve_reduce(oracle: &mut LpnOracle, b: u32, weight: u32, list_size: usize) {
+    let k = oracle.get_k();
+    let b = b as usize;
+    let n = oracle.samples.len();
+    assert!(b < k, "b < k");
+    assert!(b <= 64, "The window should fit in a u64");
+    assert!(
+        (weight as usize) < b,
+        "The weight should be smaller than the window"
+    );
+    assert!(
+        weight == 0 || oracle.delta_s > 0.0,
+        "Sieving with a nonzero weight only works for sparse secrets!"
+    );
+    assert!(n > 1, "Need samples to sieve");
+
+    // bucket on about log2(n) positions so random samples rarely share a bucket
+    let hash_bits = std::cmp::min(log_2(n) as usize, b - weight as usize).max(1);
+    let collision_probability = (0..hash_bits)
+        .map(|i| (b - weight as usize - i) as f64 / (b - i) as f64)
+        .product::<f64>();
+    let max_repetitions = (4.0 / collision_probability).ceil() as usize;
+    log::info!(
+        "Sieving on {} bits for weight <= {}, {} samples (2^{}), hashing on {} bits, at most {} repetitions",
+        b,
+        weight,
+        n,
+        log_2(n),
+        hash_bits,
+        max_repetitions
+    );
+
+    let bitrange: ops::Range<usize> = (k - b)..k;
+    let windows = with_samples!(&oracle.samples, samples => samples
+        .par_iter()
+        .map(|q| query_bits_range(q, bitrange.clone()))
+        .collect::<Vec<u64>>());
+    let mut rng = lpn_thread_rng();
+    let mut order = (0..n).collect::<Vec<usize>>();
+    let mut seen = FnvHashSet::default();
+    // the pairs to merge into new samples
+    let mut merged = Vec::with_capacity(list_size);
+    let mut weight_counts = vec![0usize; weight as usize + 1];
+
+    for repetition in 0..max_repetitions {
+        // hash by a random subset of the window
+        let mask = rand::seq::index::sample(&mut rng, b, hash_bits)
+            .into_iter()
+            .fold(0u64, |mask, pos| mask | 1 << pos);
+        order.par_sort_unstable_by_key(|&i| windows[i] & mask);
+        let mut buckets = Vec::new();
+        let mut start = 0;
+        for end in 1..=n {
+            if end == n || windows[order[end]] & mask != windows[order[start]] & mask {
+                if end - start > 1 {
+                    buckets.push(start..end);
+                }
+                start = end;
+            }
+        }
+
+        let pairs = buckets
+            .into_par_iter()
+            .flat_map_iter(|bucket| {
+                let bucket = &order[bucket];
+                let windows = &windows;
+                bucket.iter().enumerate().flat_map(move |(idx, &i)| {
+                    bucket[(idx + 1)..].iter().filter_map(move |&j| {
+                        if (windows[i] ^ windows[j]).count_ones() <= weight {
+                            Some((i.min(j), i.max(j)))
+                        } else {
+                            None
+                        }
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for (i, j) in pairs {
+            if merged.len() >= list_size {
+                break;
+            }
+            if seen.insert((i, j)) {
+                weight_counts[(windows[i] ^ windows[j]).count_ones() as usize] += 1;
+                merged.push((i, j));
+            }
+        }
+        if merged.len() >= list_size {
+            log::debug!("List full after {} repetitions", repetition + 1);
+            break;
+        }
+    }
+
+    if merged.len() < list_size {
+        log::warn!(
+            "Only found {} of the {} requested samples",
+            merged.len(),
+            list_size
+        );
+    }
+    assert!(!merged.is_empty(), "Found no close pairs at all");
+    log::debug!(
+        "Distribution of the weights on the window: {:?}",
+        weight_counts
+    );
+
+    let window_bias = weight_counts
+        .iter()
+        .enumerate()
+        .map(|(wt, &count)| count as f64 * oracle.delta_s.powi(wt as i32))
+        .sum::<f64>()
+        / merged.len() as f64;
+
+    with_samples!(&mut oracle.samples, samples => {
+        *samples = merged
+            .par_iter()
+            .map(|&(i, j)| {
+                let mut sample = samples[i].clone();
+                sample.xor_into(&samples[j]);
+                sample
+            })
+            .collect();
+    });
+    oracle.truncate(k - b);
+    oracle.delta = oracle.delta.powi(2) * window_bias;
+    log::debug!(
+        "Sieving done, {} samples (2^{}) now, k' = {}, new delta = {}",
+        oracle.samples.len(),
+        log_2(oracle.samples.len()),
+        oracle.get_k(),
+        oracle.delta
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{lf1::fwht_solve, oracle::Sample};
+    use m4ri_rust::friendly::BinVector;
+
+    #[test]
+    fn run_sieve() {
+        let mut secret = BinVector::from_elem(20, false);
+        secret.set(3, true);
+        secret.set(8, true);
+        secret.set(15, true);
+        let secret = Sample::from_binvector(&secret, false);
+        let mut oracle = LpnOracle::new_with_secret(secret, 20, 1.0 / 16.0);
+        oracle.delta_s = 0.75;
+        oracle.get_samples(20_000);
+        let secret = oracle.secret.clone();
+
+        sieve_reduce(&mut oracle, 10, 1, 20_000);
+        assert_eq!(oracle.get_k(), 10);
+        assert_eq!(oracle.samples.len(), 20_000);
+        assert!(oracle.delta < 0.875f64.powi(2));
+        assert_eq!(fwht_solve(oracle), secret.as_binvector(10));
+    }
+}
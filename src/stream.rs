@@ -0,0 +1,176 @@
+//! Lazy streams of samples, to avoid materialising the samples of the last reduction
+//!
+//! A [`SampleStream`] produces the samples in chunks, either from samples already in an oracle
+//! or by querying new ones, and applies per-sample reductions to every chunk as it passes.
+//! A streaming solver such as [`fwht_solve_streaming`](crate::lf1::fwht_solve_streaming)
+//! then only needs to hold one chunk next to its counters.
+//! The chunks are in the layout of `MAX_K`, whatever the layout of the samples of the oracle.
+use crate::{
+    oracle::{with_samples, LpnOracle, Sample, Samples},
+    util::log_2,
+};
+use rayon::prelude::*;
+
+/// The default number of samples in a chunk
+pub const DEFAULT_CHUNK_SIZE: usize = 1 << 16;
+
+type Stage<'a> = Box<dyn Fn(&mut Sample) + Send + Sync + 'a>;
+
+/// A stream of samples that produces chunks on demand
+///
+/// First produces the samples that were in the oracle, then queries new samples.
+/// The stages added with [`SampleStream::map`] are applied to every sample in order.
+pub struct SampleStream<'a> {
+    /// The samples of the oracle that are still to produce, released as they are handed out
+    samples: Samples,
+    /// Queries the new samples, holds no samples between chunks
+    source: LpnOracle,
+    /// The number of samples still to query
+    remaining: usize,
+    chunk_size: usize,
+    stages: Vec<Stage<'a>>,
+    /// Describes the samples after all stages, never holds samples
+    oracle: LpnOracle,
+}
+
+impl<'a> SampleStream<'a> {
+    /// Stream the samples in `oracle` followed by `n` newly queried samples
+    pub fn new(mut source: LpnOracle, n: usize) -> SampleStream<'a> {
+        let samples = source.samples.take();
+        let oracle = source.clone();
+        log::debug!(
+            "Streaming {} samples and {} (2^{}) new ones",
+            samples.len(),
+            n,
+            log_2(n)
+        );
+        SampleStream {
+            samples,
+            source,
+            remaining: n,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            stages: Vec::new(),
+            oracle,
+        }
+    }
+
+    /// Set the number of samples per chunk
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> SampleStream<'a> {
+        assert!(chunk_size > 0, "Chunks can't be empty");
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// The oracle describing the samples produced by this stream
+    ///
+    /// Its `samples` are always empty, but `k`, `secret` and `delta` match the streamed samples.
+    pub fn oracle(&self) -> &LpnOracle {
+        &self.oracle
+    }
+
+    /// Mutable access to the oracle describing the samples, for reductions
+    pub fn oracle_mut(&mut self) -> &mut LpnOracle {
+        &mut self.oracle
+    }
+
+    /// The number of samples this stream will still produce
+    pub fn remaining(&self) -> usize {
+        self.samples.len() + self.remaining
+    }
+
+    /// Apply `stage` to every sample produced
+    pub fn map<F>(mut self, stage: F) -> SampleStream<'a>
+    where
+        F: Fn(&mut Sample) + Send + Sync + 'a,
+    {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    /// Produce all the samples and store them in the oracle
+    pub fn into_oracle(self) -> LpnOracle {
+        let mut oracle = self.oracle.clone();
+        let remaining = self.remaining();
+        with_samples!(&mut oracle.samples, samples => samples.reserve_exact(remaining));
+        for chunk in self {
+            oracle.samples.extend(chunk);
+        }
+        oracle
+    }
+}
+
+impl<'a> Iterator for SampleStream<'a> {
+    type Item = Vec<Sample>;
+
+    fn next(&mut self) -> Option<Vec<Sample>> {
+        let chunk_size = self.chunk_size;
+        let mut chunk = if !self.samples.is_empty() {
+            with_samples!(&mut self.samples, samples => {
+                let chunk = samples.split_off(samples.len().saturating_sub(chunk_size));
+                // split_off keeps the capacity, so release it once half of it is unused
+                if samples.capacity() > 2 * samples.len() {
+                    samples.shrink_to_fit();
+                }
+                chunk.par_iter().map(Sample::resize).collect::<Vec<Sample>>()
+            })
+        } else if self.remaining > 0 {
+            let n = std::cmp::min(self.remaining, chunk_size);
+            self.remaining -= n;
+            self.source.get_samples(n);
+            let chunk = self.source.samples.to_vec();
+            self.source.samples.clear();
+            chunk
+        } else {
+            return None;
+        };
+        let stages = &self.stages;
+        if !stages.is_empty() {
+            chunk
+                .par_iter_mut()
+                .for_each(|sample| stages.iter().for_each(|stage| stage(sample)));
+        }
+        Some(chunk)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let chunks =
+            self.samples.len().div_ceil(self.chunk_size) + self.remaining.div_ceil(self.chunk_size);
+        (chunks, Some(chunks))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stream_chunks() {
+        let mut oracle = LpnOracle::new(20, 1.0 / 8.0);
+        oracle.set_query_budget(2500);
+        oracle.get_samples(500);
+        let stream = SampleStream::new(oracle, 2000)
+            .with_chunk_size(300)
+            .map(|sample| sample.set_bit(0, false));
+        assert_eq!(stream.remaining(), 2500);
+        assert_eq!(stream.size_hint(), (9, Some(9)));
+        let chunks = stream.collect::<Vec<_>>();
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 300));
+        assert_eq!(chunks.iter().map(Vec::len).sum::<usize>(), 2500);
+        assert!(chunks.iter().flatten().all(|sample| !sample.get_bit(0)));
+    }
+
+    #[test]
+    fn stream_releases_samples() {
+        let mut oracle = LpnOracle::new(20, 1.0 / 8.0);
+        oracle.get_samples(10_000);
+        let mut stream = SampleStream::new(oracle, 0).with_chunk_size(1000);
+        let capacity =
+            |stream: &SampleStream| with_samples!(&stream.samples, samples => samples.capacity());
+        while let Some(chunk) = stream.next() {
+            assert_eq!(chunk.len(), 1000);
+            assert!(stream.source.samples.is_empty());
+            assert!(capacity(&stream) <= 2 * stream.samples.len());
+        }
+        assert_eq!(capacity(&stream), 0);
+    }
+}
@@ -0,0 +1,358 @@
+//! Convert LPN problems to and from syndrome-decoding problems
+//!
+//! LPN with `n` samples is equivalent to decoding a random `[n, k]` code:
+//! the samples `A s + e = b` give the generator matrix `A^T` and the received word `b`.
+//! We write the parity-check matrix in systematic form `H = (I_{n-k} | X)`,
+//! which allows reading and writing instances in the format used by
+//! the syndrome decoding challenge at <https://decodingchallenge.org>.
+use crate::{
+    oracle::{with_samples, LpnOracle, Sample, MAX_K},
+    random::lpn_thread_rng,
+};
+use m4ri_rust::friendly::BinMatrix;
+use m4ri_rust::friendly::BinVector;
+use std::io::{self, BufRead, Write};
+
+/// A syndrome-decoding instance: find `e` of weight `weight` such that `H e = syndrome`
+///
+/// The parity-check matrix is `H = (I_{n-k} | X)`, of which we only store `X`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyndromeDecodingInstance {
+    /// The non-identity part `X` of the parity-check matrix, an `(n-k) x k` matrix
+    pub redundancy: BinMatrix,
+    /// The syndrome, of length `n-k`
+    pub syndrome: BinVector,
+    /// The target weight of the error
+    pub weight: usize,
+    /// For every column of `H`, the index of the corresponding sample in the oracle
+    ///
+    /// The last `k` columns form the information set.
+    pub sample_order: Vec<usize>,
+}
+
+impl SyndromeDecodingInstance {
+    /// The length `n` of the code
+    pub fn length(&self) -> usize {
+        self.redundancy.nrows() + self.redundancy.ncols()
+    }
+
+    /// The dimension `k` of the code
+    pub fn dimension(&self) -> usize {
+        self.redundancy.ncols()
+    }
+
+    /// Get the full parity-check matrix `H = (I_{n-k} | X)`
+    pub fn parity_check_matrix(&self) -> BinMatrix {
+        BinMatrix::identity(self.redundancy.nrows()).augmented(&self.redundancy)
+    }
+
+    /// Write the instance in the format of the syndrome decoding challenge
+    pub fn write_challenge<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let n = self.length();
+        let k = self.dimension();
+        writeln!(writer, "# n")?;
+        writeln!(writer, "{}", n)?;
+        writeln!(writer, "# seed")?;
+        writeln!(writer, "0")?;
+        writeln!(writer, "# w")?;
+        writeln!(writer, "{}", self.weight)?;
+        writeln!(
+            writer,
+            "# H^transpose (each line corresponds to column of H, the identity part is omitted)"
+        )?;
+        for col in 0..k {
+            writeln!(
+                writer,
+                "{}",
+                bit_string((0..(n - k)).map(|row| self.redundancy.bit(row, col)))
+            )?;
+        }
+        writeln!(writer, "# s^transpose")?;
+        writeln!(writer, "{}", bit_string(self.syndrome.iter()))
+    }
+
+    /// Read an instance in the format of the syndrome decoding challenge
+    pub fn read_challenge<R: BufRead>(reader: R) -> io::Result<SyndromeDecodingInstance> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_owned());
+        let parse_bits = |line: &str| -> io::Result<BinVector> {
+            let mut vec = BinVector::with_capacity(line.len());
+            for c in line.chars() {
+                match c {
+                    '0' => vec.push(false),
+                    '1' => vec.push(true),
+                    _ => return Err(invalid("expected a line of bits")),
+                }
+            }
+            Ok(vec)
+        };
+
+        let lines = reader
+            .lines()
+            .collect::<io::Result<Vec<String>>>()?
+            .into_iter()
+            .map(|line| line.trim().to_owned())
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>();
+
+        let mut n = None;
+        let mut weight = None;
+        let mut columns = Vec::new();
+        let mut syndrome = None;
+        let mut section = "";
+        for line in &lines {
+            if let Some(header) = line.strip_prefix('#') {
+                section = match header.split_whitespace().next() {
+                    Some("n") => "n",
+                    Some("seed") => "seed",
+                    Some("w") => "w",
+                    Some("H^transpose") => "H",
+                    Some("s^transpose") => "s",
+                    _ => return Err(invalid("unknown section")),
+                };
+                continue;
+            }
+            match section {
+                "n" => n = Some(line.parse().map_err(|_| invalid("invalid n"))?),
+                "w" => weight = Some(line.parse().map_err(|_| invalid("invalid w"))?),
+                "H" => columns.push(parse_bits(line)?),
+                "s" => syndrome = Some(parse_bits(line)?),
+                _ => (),
+            }
+        }
+
+        let n: usize = n.ok_or_else(|| invalid("missing n"))?;
+        let weight = weight.ok_or_else(|| invalid("missing w"))?;
+        let syndrome = syndrome.ok_or_else(|| invalid("missing s"))?;
+        let k = columns.len();
+        if k == 0 || k >= n || syndrome.len() != n - k || columns.iter().any(|c| c.len() != n - k) {
+            return Err(invalid("dimensions of H and s do not match n"));
+        }
+
+        Ok(SyndromeDecodingInstance {
+            redundancy: BinMatrix::new(columns).transposed(),
+            syndrome,
+            weight,
+            sample_order: (0..n).collect(),
+        })
+    }
+}
+
+/// Export the samples of the oracle as a syndrome-decoding instance
+///
+/// Picks `k` linearly independent samples as information set.
+/// The target weight is the expected weight of the noise.
+pub fn to_syndrome_decoding(oracle: &LpnOracle) -> SyndromeDecodingInstance {
+    let k = oracle.get_k();
+    let n = oracle.samples.len();
+    assert!(n > k, "We need more than k samples");
+    let mut rng = lpn_thread_rng();
+
+    // find information set
+    let info_set = loop {
+        let idxs = rand::seq::index::sample(&mut rng, n, k).into_vec();
+        let a_info = BinMatrix::new(with_samples!(&oracle.samples, samples => idxs
+            .iter()
+            .map(|&idx| samples[idx].as_binvector(k))
+            .collect()));
+        if a_info.clone().echelonize() == k {
+            break idxs;
+        }
+    };
+    let mut in_info_set = vec![false; n];
+    info_set.iter().for_each(|&idx| in_info_set[idx] = true);
+    let rest = (0..n).filter(|&idx| !in_info_set[idx]).collect::<Vec<_>>();
+
+    let (a_info, b_info) = split_samples(oracle, &info_set);
+    let (a_rest, b_rest) = split_samples(oracle, &rest);
+
+    // H (A_rest; A_info) = A_rest + X A_info = 0
+    let redundancy = &a_rest * &a_info.inverted();
+    let syndrome = (&(&redundancy * &b_info) + &b_rest).as_vector();
+
+    let tau = (1.0 - oracle.delta) / 2.0;
+    let weight = (tau * n as f64).round() as usize;
+    log::info!(
+        "Exported [{}, {}] syndrome decoding instance with target weight {}",
+        n,
+        k,
+        weight
+    );
+
+    let mut sample_order = rest;
+    sample_order.extend(info_set);
+    SyndromeDecodingInstance {
+        redundancy,
+        syndrome,
+        weight,
+        sample_order,
+    }
+}
+
+/// Import a syndrome-decoding instance as an LPN problem
+///
+/// The samples are the rows of the generator matrix `(X; I_k)` with products `(s, 0)`.
+/// The secret of the returned oracle is unknown, see [`LpnOracle::is_secret_known`].
+/// Panics if the dimension of the code is not below `MAX_K`.
+pub fn from_syndrome_decoding(instance: &SyndromeDecodingInstance) -> LpnOracle {
+    let n = instance.length();
+    let k = instance.dimension();
+    let tau = instance.weight as f64 / n as f64;
+    assert!(
+        0 < k && k < MAX_K,
+        "Can only import codes of dimension 0 < k < {} in this build, got k = {}",
+        MAX_K,
+        k
+    );
+
+    let mut oracle = LpnOracle::new(k as u32, tau);
+    oracle.forget_secret();
+    with_samples!(&mut oracle.samples, samples => {
+        samples.reserve_exact(n);
+        samples.extend((0..(n - k)).map(|row| {
+            Sample::from_binvector(
+                &instance
+                    .redundancy
+                    .get_window(row, 0, row + 1, k)
+                    .as_vector(),
+                instance.syndrome.get(row).unwrap(),
+            )
+        }));
+        samples.extend((0..k).map(|col| {
+            let mut sample = Sample::new();
+            sample.set_bit(col, true);
+            sample
+        }));
+    });
+    oracle
+}
+
+/// Recover the LPN secret from a solution `e` of the syndrome-decoding instance
+///
+/// The error is indexed like the columns of `H`.
+pub fn recover_secret(
+    oracle: &LpnOracle,
+    instance: &SyndromeDecodingInstance,
+    error: &BinVector,
+) -> BinVector {
+    let n = instance.length();
+    let k = instance.dimension();
+    assert_eq!(error.len(), n, "error should have length n");
+    let info_set = &instance.sample_order[(n - k)..];
+    let (a_info, b_info) = split_samples(oracle, info_set);
+    let e_info = BinVector::from_function(k, |i| error.get(n - k + i).unwrap());
+
+    // A_info s = b_info + e_info
+    &a_info.inverted() * &(&b_info.as_vector() + &e_info)
+}
+
+/// Render bits as a string of `0`s and `1`s
+fn bit_string<I: Iterator<Item = bool>>(bits: I) -> String {
+    bits.map(|bit| if bit { '1' } else { '0' }).collect()
+}
+
+/// Collect the selected samples as `(A, b)`
+fn split_samples(oracle: &LpnOracle, idxs: &[usize]) -> (BinMatrix, BinMatrix) {
+    let k = oracle.get_k();
+    let mut b = BinVector::with_capacity(idxs.len());
+    let a = BinMatrix::new(with_samples!(&oracle.samples, samples => idxs
+        .iter()
+        .map(|&idx| {
+            let sample = &samples[idx];
+            b.push(sample.get_product());
+            sample.as_binvector(k)
+        })
+        .collect()));
+    (a, b.as_column_matrix())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gauss::pooled_gauss_solve;
+
+    #[test]
+    fn roundtrip_challenge_format() {
+        let mut oracle = LpnOracle::new(20, 1.0 / 8.0);
+        oracle.get_samples(60);
+        let instance = to_syndrome_decoding(&oracle);
+        assert_eq!(instance.length(), 60);
+        assert_eq!(instance.dimension(), 20);
+
+        let mut buffer = Vec::new();
+        instance.write_challenge(&mut buffer).unwrap();
+        let read = SyndromeDecodingInstance::read_challenge(&buffer[..]).unwrap();
+        assert_eq!(read.redundancy, instance.redundancy);
+        assert_eq!(read.syndrome, instance.syndrome);
+        assert_eq!(read.weight, instance.weight);
+    }
+
+    #[test]
+    fn error_gives_secret() {
+        let mut oracle = LpnOracle::new(20, 1.0 / 8.0);
+        oracle.get_samples(60);
+        let secret = oracle.secret.as_binvector(20);
+        let instance = to_syndrome_decoding(&oracle);
+
+        // compute the actual error in the order of the columns of H
+        let samples = oracle.samples.to_vec();
+        let error = BinVector::from_function(instance.length(), |col| {
+            let sample = &samples[instance.sample_order[col]];
+            sample.vector_product(&oracle.secret, 20) != sample.get_product()
+        });
+        assert_eq!(
+            &instance.parity_check_matrix() * &error,
+            instance.syndrome,
+            "H e != s"
+        );
+        assert_eq!(recover_secret(&oracle, &instance, &error), secret);
+
+        // importing gives an equivalent problem
+        let imported = from_syndrome_decoding(&instance);
+        let e_info = BinVector::from_function(20, |i| error.get(40 + i).unwrap());
+        for (col, sample) in imported.samples.to_vec().iter().enumerate() {
+            let product = sample.vector_product(&<Sample>::from_binvector(&e_info, false), 20);
+            assert_eq!(product ^ error.get(col).unwrap(), sample.get_product());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Can only import codes of dimension")]
+    fn import_too_large() {
+        let instance = SyndromeDecodingInstance {
+            redundancy: BinMatrix::zero(10, MAX_K),
+            syndrome: BinVector::from_elem(10, false),
+            weight: 1,
+            sample_order: (0..MAX_K + 10).collect(),
+        };
+        from_syndrome_decoding(&instance);
+    }
+
+    #[test]
+    fn solve_imported() {
+        let mut oracle = LpnOracle::new(20, 1.0 / 16.0);
+        oracle.get_samples(1000);
+        let instance = to_syndrome_decoding(&oracle);
+        let mut buffer = Vec::new();
+        instance.write_challenge(&mut buffer).unwrap();
+        let mut read = SyndromeDecodingInstance::read_challenge(&buffer[..]).unwrap();
+        read.sample_order = instance.sample_order.clone();
+
+        let imported = from_syndrome_decoding(&read);
+        assert!(!imported.is_secret_known());
+        // the secret of the imported problem is the error on the information set
+        let e_info = pooled_gauss_solve(imported);
+        let error = BinVector::from_function(read.length(), |col| {
+            if col < 980 {
+                let row = read.redundancy.get_window(col, 0, col + 1, 20).as_vector();
+                (&row * &e_info) ^ read.syndrome.get(col).unwrap()
+            } else {
+                e_info.get(col - 980).unwrap()
+            }
+        });
+        assert_eq!(&read.parity_check_matrix() * &error, read.syndrome);
+        assert_eq!(
+            recover_secret(&oracle, &read, &error),
+            oracle.secret.as_binvector(20)
+        );
+    }
+}